@@ -0,0 +1,135 @@
+use std::error::Error;
+use std::fmt;
+use std::io;
+use std::path::{Path, PathBuf};
+
+/// Errors of reading and packing archives.
+#[derive(Debug)]
+pub enum HabError {
+    /// The archive doesn't start with `HAB` followed by a version.
+    BadMagic {
+        found: Vec<u8>,
+    },
+    /// The archive has a version whose layout isn't known.
+    UnsupportedVersion(u8),
+    /// The archive ends before its header, entry table or names do.
+    Truncated,
+    InvalidEntryIndex(usize),
+    NameNotUtf8 {
+        index: usize,
+    },
+    /// The name of an entry doesn't lie within the names in front of the data,
+    /// `offset` being relative to the start of the names.
+    NameOutOfBounds {
+        index: usize,
+        offset: u64,
+    },
+    /// There is no entry with this name, `close_matches` are the names that look similar.
+    NoSuchEntry {
+        name: String,
+        close_matches: Vec<String>,
+    },
+    Io(io::Error),
+    /// A file other than the archive being read couldn't be read or written.
+    File {
+        path: PathBuf,
+        source: io::Error,
+    },
+    /// A manifest or other JSON file written next to the extracted entries is invalid.
+    Json {
+        path: PathBuf,
+        source: serde_json::Error,
+    },
+    /// The template archive given to `pack` can't be read.
+    Template {
+        path: PathBuf,
+        source: Box<HabError>,
+    },
+    /// A file to pack has a path that isn't UTF-8, so it can't be an entry name.
+    PathNotUtf8(PathBuf),
+    /// There are more files to pack than an archive can hold.
+    TooManyEntries,
+    /// The files to pack don't fit into the 32 bit offsets of an archive.
+    TooLarge,
+    /// A file to pack changed its size while it was being packed.
+    SizeChanged(PathBuf),
+}
+
+impl HabError {
+    /// Attributes an I/O error to `path`, for use with `map_err`.
+    pub(crate) fn file(path: &Path) -> impl FnOnce(io::Error) -> Self + '_ {
+        move |source| Self::File {
+            path: path.to_owned(),
+            source,
+        }
+    }
+
+    /// Attributes a JSON error to `path`, for use with `map_err`.
+    pub(crate) fn json(path: &Path) -> impl FnOnce(serde_json::Error) -> Self + '_ {
+        move |source| Self::Json {
+            path: path.to_owned(),
+            source,
+        }
+    }
+}
+
+impl fmt::Display for HabError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::BadMagic { found } => write!(
+                f,
+                "expected magic \"HAB0\", found {:?}",
+                found.escape_ascii().to_string()
+            ),
+            Self::UnsupportedVersion(version) => {
+                write!(f, "unsupported HAB version {}", version.escape_ascii())
+            }
+            Self::Truncated => write!(f, "the archive is truncated"),
+            Self::InvalidEntryIndex(index) => write!(f, "invalid entry index {index}"),
+            Self::NameNotUtf8 { index } => write!(f, "the name of entry {index} is not UTF-8"),
+            Self::NameOutOfBounds { index, offset } => write!(
+                f,
+                "the name of entry {index} at offset {offset:#x} lies outside of the names"
+            ),
+            Self::NoSuchEntry {
+                name,
+                close_matches,
+            } => match close_matches.is_empty() {
+                true => write!(f, "the archive has no entry named {name:?}"),
+                false => write!(
+                    f,
+                    "the archive has no entry named {name:?}, did you mean {}?",
+                    close_matches.join(", ")
+                ),
+            },
+            Self::Io(_) => write!(f, "failed to read the archive"),
+            Self::File { path, .. } | Self::Json { path, .. } | Self::Template { path, .. } => {
+                write!(f, "{path:?}")
+            }
+            Self::PathNotUtf8(path) => write!(f, "{path:?} is not UTF-8"),
+            Self::TooManyEntries => write!(f, "too many files for one archive"),
+            Self::TooLarge => write!(f, "archive is too large"),
+            Self::SizeChanged(path) => write!(f, "{path:?} changed its size while packing"),
+        }
+    }
+}
+
+impl Error for HabError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            Self::Io(err) | Self::File { source: err, .. } => Some(err),
+            Self::Json { source, .. } => Some(source),
+            Self::Template { source, .. } => Some(source),
+            _ => None,
+        }
+    }
+}
+
+impl From<io::Error> for HabError {
+    fn from(err: io::Error) -> Self {
+        match err.kind() {
+            io::ErrorKind::UnexpectedEof => Self::Truncated,
+            _ => Self::Io(err),
+        }
+    }
+}
@@ -0,0 +1,480 @@
+//! Reading and writing of HAB0 archives.
+
+use std::collections::HashMap;
+use std::io::{self, BufRead, Read, Seek, SeekFrom, Write};
+
+use flate2::bufread::ZlibDecoder;
+use serde::{Deserialize, Serialize, Serializer};
+use totd_io::{BinaryReader, Endian};
+use tracing::trace;
+
+pub use crate::error::HabError;
+pub use crate::manifest::{Manifest, ManifestEntry};
+pub use crate::pack::{NORMALIZED_NAMES_FILE_NAME, pack};
+
+mod error;
+mod manifest;
+mod pack;
+
+pub type Result<T, E = HabError> = std::result::Result<T, E>;
+
+/// A HAB0 archive read through `R`.
+#[derive(Debug, Serialize)]
+pub struct Hab<R> {
+    #[serde(skip)]
+    reader: R,
+    pub entries: Vec<FileEntry>,
+    /// Size of the whole archive in bytes.
+    pub total_size: u32,
+    /// Position of the data section, which the entries' data offsets are relative to.
+    pub data_start: u64,
+    #[serde(serialize_with = "serialize_hex")]
+    _unk0: Vec<u8>,
+    _unk1: u16,
+    _unk2: u32,
+}
+
+impl<R> Hab<R>
+where
+    R: BufRead + Seek,
+{
+    pub fn new(mut reader: R) -> Result<Self> {
+        let magic = reader.read_bytes(4)?;
+
+        if magic[..3] != *b"HAB" {
+            return Err(HabError::BadMagic { found: magic });
+        }
+
+        // Only the layout of version 0 is known so far
+        match magic[3] {
+            b'0' => {}
+            version => return Err(HabError::UnsupportedVersion(version)),
+        }
+
+        let _unk0 = reader.read_bytes(16)?;
+        let num_entries = reader.read_u16(Endian::Little)? as usize;
+        let _unk1 = reader.read_u16(Endian::Little)?;
+        let _unk2 = reader.read_u32(Endian::Little)?;
+        let total_size = reader.read_u32(Endian::Little)?;
+        let mut file_metas = Vec::new();
+
+        for _ in 0..num_entries {
+            let entry = FileMeta::from_reader(&mut reader)?;
+
+            file_metas.push(entry);
+        }
+
+        trace!("{file_metas:#?}");
+
+        let filenames_start = reader.stream_position()?;
+        let archive_len = reader.seek(SeekFrom::End(0))?;
+        let mut entries = Vec::new();
+
+        // The data section fills the rest of the archive after the names, so the names have
+        // to end where it starts. The recorded size still holds for truncated archives, and
+        // padding after the data only moves the bound further into the archive.
+        let data_len = file_metas
+            .iter()
+            .map(|meta| meta.data_offset + meta.data_size)
+            .max()
+            .unwrap_or(0);
+        let names_end = archive_len
+            .max(total_size.into())
+            .checked_sub(data_len)
+            .filter(|&names_end| names_end >= filenames_start)
+            .unwrap_or(archive_len);
+
+        reader.seek(SeekFrom::Start(filenames_start))?;
+
+        for (index, meta) in file_metas.into_iter().enumerate() {
+            let out_of_bounds = HabError::NameOutOfBounds {
+                index,
+                offset: meta.name_offset,
+            };
+
+            if filenames_start + meta.name_offset >= archive_len {
+                return Err(out_of_bounds);
+            }
+
+            reader.seek(SeekFrom::Start(filenames_start + meta.name_offset))?;
+            let name = reader.read_cstring().map_err(|err| match err.kind() {
+                io::ErrorKind::InvalidData => HabError::NameNotUtf8 { index },
+                io::ErrorKind::UnexpectedEof => out_of_bounds,
+                _ => err.into(),
+            })?;
+
+            entries.push(FileEntry { name, meta });
+        }
+
+        let mut data_start = filenames_start;
+
+        // The names are stored back to back in front of the data, so any name
+        // reaching into it has a corrupt offset
+        for (index, entry) in entries.iter().enumerate() {
+            let name_end = filenames_start + entry.meta.name_offset + entry.name.len() as u64 + 1;
+
+            if name_end > names_end {
+                return Err(HabError::NameOutOfBounds {
+                    index,
+                    offset: entry.meta.name_offset,
+                });
+            }
+
+            data_start = data_start.max(name_end);
+        }
+
+        Ok(Self {
+            reader,
+            total_size,
+            entries,
+            data_start,
+            _unk0,
+            _unk1,
+            _unk2,
+        })
+    }
+
+    /// Compares the sizes recorded in the archive with its actual length.
+    pub fn check_size(&mut self) -> Result<SizeCheck> {
+        let actual_size = self.reader.seek(SeekFrom::End(0))?;
+        let expected_size = self.data_start
+            + self
+                .entries
+                .iter()
+                .map(|entry| entry.meta.data_size)
+                .sum::<u64>();
+        let mut problems = Vec::new();
+
+        if u64::from(self.total_size) != actual_size {
+            problems.push(format!(
+                "header says the archive is {} bytes, but it is {actual_size} bytes",
+                self.total_size
+            ));
+        }
+
+        if expected_size > actual_size {
+            problems.push(format!(
+                "entries need {expected_size} bytes, but the archive is only {actual_size} bytes, it looks truncated"
+            ));
+        }
+
+        for (index, entry) in self.entries.iter().enumerate() {
+            let start = self.data_start + entry.meta.data_offset;
+            let end = start + entry.meta.data_size;
+
+            if end > actual_size {
+                problems.push(format!(
+                    "entry {index} ({}) wants bytes {start:#x}..{end:#x}, but the archive is only {actual_size:#x} bytes",
+                    entry.name
+                ));
+            }
+        }
+
+        Ok(SizeCheck {
+            expected_size,
+            actual_size,
+            problems,
+        })
+    }
+
+    /// The header fields whose meaning is unknown, in the order they appear in.
+    pub fn unknown_fields(&self) -> (&[u8], u16, u32) {
+        (&self._unk0, self._unk1, self._unk2)
+    }
+
+    pub fn num_entries(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn get_file_by_index(&mut self, index: usize) -> Result<HabFile<'_, &mut R>> {
+        let entry = self
+            .entries
+            .get(index)
+            .ok_or(HabError::InvalidEntryIndex(index))?;
+
+        HabFile::new(&mut self.reader, entry, self.data_start)
+    }
+
+    /// Like `get_file_by_index`, but reads through `reader` instead of the
+    /// archive's own reader, so several entries can be read at the same time.
+    ///
+    /// `reader` has to read the same archive.
+    pub fn read_file_by_index<S>(&self, index: usize, reader: S) -> Result<HabFile<'_, S>>
+    where
+        S: BufRead + Seek,
+    {
+        let entry = self
+            .entries
+            .get(index)
+            .ok_or(HabError::InvalidEntryIndex(index))?;
+
+        HabFile::new(reader, entry, self.data_start)
+    }
+
+    /// Reads the data of every entry into memory, keyed by name.
+    ///
+    /// Entries sharing a name get their index appended to it, like `name.3`,
+    /// the same way they are named when they are extracted.
+    pub fn read_all(&mut self) -> Result<HashMap<String, Vec<u8>>> {
+        let mut name_counts = HashMap::<&str, usize>::new();
+
+        for entry in &self.entries {
+            *name_counts.entry(&entry.name).or_default() += 1;
+        }
+
+        let keys = self
+            .entries
+            .iter()
+            .enumerate()
+            .map(|(index, entry)| match name_counts[entry.name.as_str()] {
+                1 => entry.name.clone(),
+                _ => format!("{}.{index}", entry.name),
+            })
+            .collect::<Vec<_>>();
+        let mut files = HashMap::with_capacity(keys.len());
+
+        for (index, key) in keys.into_iter().enumerate() {
+            let mut hab_file = self.get_file_by_index(index)?;
+            let data_size = hab_file.entry().meta.data_size;
+            let mut data = Vec::with_capacity(data_size as usize);
+
+            if io::copy(&mut hab_file, &mut data)? < data_size {
+                return Err(HabError::Truncated);
+            }
+
+            files.insert(key, data);
+        }
+
+        Ok(files)
+    }
+
+    pub fn entry_index(&self, name: &str) -> Option<usize> {
+        self.entries.iter().position(|entry| entry.name == name)
+    }
+
+    pub fn get_file_by_name(&mut self, name: &str) -> Result<HabFile<'_, &mut R>> {
+        let index = self.find_entry(name)?;
+
+        self.get_file_by_index(index)
+    }
+
+    /// Like `entry_index`, but fails with a list of close matches if there is no such entry.
+    pub fn find_entry(&self, name: &str) -> Result<usize> {
+        self.entry_index(name).ok_or_else(|| HabError::NoSuchEntry {
+            name: name.to_owned(),
+            close_matches: self
+                .entries
+                .iter()
+                .map(|entry| entry.name.clone())
+                .filter(|candidate| is_close_match(name, candidate))
+                .take(5)
+                .collect(),
+        })
+    }
+}
+
+/// Whether `candidate` looks like what was meant by a misspelled `name`.
+fn is_close_match(name: &str, candidate: &str) -> bool {
+    let name = name.to_lowercase();
+    let candidate = candidate.to_lowercase();
+
+    candidate.contains(&name) || edit_distance(&name, &candidate) <= name.chars().count().max(3) / 3
+}
+
+/// Levenshtein distance between `a` and `b`.
+fn edit_distance(a: &str, b: &str) -> usize {
+    let b = b.chars().collect::<Vec<_>>();
+    let mut previous = (0..=b.len()).collect::<Vec<_>>();
+
+    for (i, a) in a.chars().enumerate() {
+        let mut current = vec![i + 1];
+
+        for (j, &b) in b.iter().enumerate() {
+            let substitution = previous[j] + usize::from(a != b);
+
+            current.push(substitution.min(previous[j + 1] + 1).min(current[j] + 1));
+        }
+
+        previous = current;
+    }
+
+    previous[b.len()]
+}
+
+/// Result of `Hab::check_size`.
+pub struct SizeCheck {
+    /// End of the data section according to the entries.
+    pub expected_size: u64,
+    pub actual_size: u64,
+    pub problems: Vec<String>,
+}
+
+/// Reader for the data of a single entry of a `Hab`.
+pub struct HabFile<'a, R> {
+    reader: io::Take<R>,
+    entry: &'a FileEntry,
+}
+
+impl<'a, R> HabFile<'a, R>
+where
+    R: BufRead + Seek,
+{
+    pub fn file_name(&self) -> &'a str {
+        &self.entry.name
+    }
+
+    pub fn entry(&self) -> &'a FileEntry {
+        self.entry
+    }
+
+    /// Whether the entry's data starts with a zlib header.
+    ///
+    /// The archive has no known flag for compressed entries, so this is only a guess.
+    pub fn looks_compressed(&mut self) -> io::Result<bool> {
+        let header = self.fill_buf()?;
+
+        Ok(match *header {
+            [cmf, flg, ..] => {
+                let is_deflate = cmf & 0x0f == 8 && cmf >> 4 <= 7;
+                let has_dictionary = flg & 0x20 != 0;
+
+                is_deflate && !has_dictionary && u16::from_be_bytes([cmf, flg]) % 31 == 0
+            }
+            _ => false,
+        })
+    }
+
+    /// Reads the entry's data through a zlib decoder if it looks compressed,
+    /// or as it is stored otherwise.
+    pub fn decompressed(mut self) -> io::Result<EntryData<'a, R>> {
+        Ok(match self.looks_compressed()? {
+            true => EntryData::Zlib(ZlibDecoder::new(self)),
+            false => EntryData::Stored(self),
+        })
+    }
+
+    fn new(mut reader: R, entry: &'a FileEntry, data_start: u64) -> Result<Self> {
+        reader.seek(SeekFrom::Start(data_start + entry.meta.data_offset))?;
+
+        Ok(Self {
+            reader: reader.take(entry.meta.data_size),
+            entry,
+        })
+    }
+}
+
+impl<R> Read for HabFile<'_, R>
+where
+    R: BufRead,
+{
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.reader.read(buf)
+    }
+}
+
+impl<R> BufRead for HabFile<'_, R>
+where
+    R: BufRead,
+{
+    fn fill_buf(&mut self) -> io::Result<&[u8]> {
+        self.reader.fill_buf()
+    }
+
+    fn consume(&mut self, amt: usize) {
+        self.reader.consume(amt);
+    }
+}
+
+/// The data of an entry, inflated if it was compressed, see `HabFile::decompressed`.
+pub enum EntryData<'a, R> {
+    Stored(HabFile<'a, R>),
+    Zlib(ZlibDecoder<HabFile<'a, R>>),
+}
+
+impl<'a, R> EntryData<'a, R> {
+    pub fn hab_file(&self) -> &HabFile<'a, R> {
+        match self {
+            Self::Stored(hab_file) => hab_file,
+            Self::Zlib(decoder) => decoder.get_ref(),
+        }
+    }
+
+    pub fn is_compressed(&self) -> bool {
+        matches!(self, Self::Zlib(_))
+    }
+}
+
+impl<R> Read for EntryData<'_, R>
+where
+    R: BufRead,
+{
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        match self {
+            Self::Stored(hab_file) => hab_file.read(buf),
+            Self::Zlib(decoder) => decoder.read(buf),
+        }
+    }
+}
+
+/// A 16 byte entry of the archive's file table.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct FileMeta {
+    pub name_offset: u64,
+    /// Offset of the entry's data, relative to `Hab::data_start`.
+    pub data_offset: u64,
+    pub data_size: u64,
+    _unk0: u32,
+}
+
+impl FileMeta {
+    /// The last field of the entry, which might be the size of the entry's data
+    /// once it is decompressed.
+    pub fn suspected_uncompressed_size(&self) -> u64 {
+        self._unk0 as u64
+    }
+
+    fn from_reader<R>(r: &mut R) -> Result<Self>
+    where
+        R: BufRead + Seek,
+    {
+        let name_offset = r.read_u32(Endian::Little)? as u64;
+        let data_offset = r.read_u32(Endian::Little)? as u64;
+        let data_size = r.read_u32(Endian::Little)? as u64;
+        let _unk0 = r.read_u32(Endian::Little)?;
+
+        Ok(Self {
+            name_offset,
+            data_offset,
+            data_size,
+            _unk0,
+        })
+    }
+
+    fn write_to<W: Write>(&self, w: &mut W) -> Result<()> {
+        for field in [self.name_offset, self.data_offset, self.data_size] {
+            let field = u32::try_from(field).map_err(|_| HabError::TooLarge)?;
+
+            w.write_all(&field.to_le_bytes())?;
+        }
+
+        w.write_all(&self._unk0.to_le_bytes())?;
+
+        Ok(())
+    }
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct FileEntry {
+    pub name: String,
+    #[serde(flatten)]
+    pub meta: FileMeta,
+}
+
+fn serialize_hex<S: Serializer>(bytes: &[u8], serializer: S) -> Result<S::Ok, S::Error> {
+    let hex = bytes
+        .iter()
+        .map(|byte| format!("{byte:02x}"))
+        .collect::<String>();
+
+    serializer.serialize_str(&hex)
+}
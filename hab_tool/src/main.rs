@@ -1,230 +1,1095 @@
-use core::slice;
-use std::fs::{self, File};
-use std::io::{self, BufRead, BufReader, Read, Seek, SeekFrom};
-use std::iter;
-use std::path::PathBuf;
+use std::collections::{BTreeMap, HashMap};
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, BufRead, BufReader, Cursor, IsTerminal, Read, Seek, Write};
+use std::path::{Component, Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
 
-use anyhow::{Context, Result, ensure};
-use clap::Parser;
+use anyhow::{Context, Result, bail, ensure};
+use clap::{ArgAction, Args, Parser, Subcommand};
+use indicatif::{ProgressBar, ProgressStyle};
+use rayon::prelude::*;
+use serde::Serialize;
+use sha2::{Digest, Sha256};
+use tracing::{Level, debug, error, info, trace, warn};
+use walkdir::WalkDir;
 
+use hab_tool::{EntryData, Hab, HabFile, Manifest, NORMALIZED_NAMES_FILE_NAME};
+
+/// Extracts and builds HAB0 archives.
 #[derive(Parser)]
+#[command(args_conflicts_with_subcommands = true, subcommand_negates_reqs = true)]
 pub struct Cli {
+    #[command(subcommand)]
+    command: Option<Command>,
+    #[command(flatten)]
+    extract: Option<ExtractArgs>,
+    /// Print more details about what is being done, up to `-vvv`
+    #[arg(short, long, action = ArgAction::Count, global = true)]
+    verbose: u8,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// Build an archive from the files of a directory
+    Pack {
+        dir: PathBuf,
+        out_file: PathBuf,
+        /// Archive to copy the unknown header fields and the entry order from,
+        /// usually the one `dir` was extracted from
+        #[arg(long, value_name = "HAB")]
+        template: Option<PathBuf>,
+    },
+    /// Check that the archive is as large as its header and entries say
+    Verify {
+        file: PathBuf,
+        /// Also check that every entry still has the SHA-256 digest recorded in this
+        /// `manifest.json` of an earlier extraction
+        #[arg(long, value_name = "JSON")]
+        manifest: Option<PathBuf>,
+    },
+    /// Print a summary of the archive's header and entries without extracting anything
+    Info { file: PathBuf },
+}
+
+/// Arguments of the default mode, extracting an archive.
+#[derive(Args)]
+struct ExtractArgs {
+    /// Archive to extract, `-` to read it from stdin, or a directory or glob pattern
+    /// matching several archives, which are each extracted into their own
+    /// subdirectory of the output directory
     file: PathBuf,
-    out_dir: PathBuf,
+    #[arg(required_unless_present_any = ["list", "stdout", "dump_meta"])]
+    out_dir: Option<PathBuf>,
+    /// Only print the entries of the archive instead of extracting them
+    #[arg(long, conflicts_with_all = ["verify", "normalize_names", "manifest"])]
+    list: bool,
+    /// Write everything read from the archive's header, including the fields of
+    /// unknown meaning, to this JSON file
+    #[arg(long, value_name = "JSON")]
+    dump_meta: Option<PathBuf>,
+    /// Verify extracted files against a `sha256sum` style checksum file
+    #[arg(long, value_name = "CHECKSUMS")]
+    verify: Option<PathBuf>,
+    /// Replace characters that are invalid in file names on common platforms and
+    /// record the renames in `normalized_names.json`
+    #[arg(long)]
+    normalize_names: bool,
+    /// Record the name, index, position and unknown fields of every entry, along
+    /// with the path and SHA-256 digest of the file it is extracted to, in
+    /// `manifest.json` in the output directory.
+    /// The source archive, time and tool version are recorded too, and `pack` uses
+    /// the manifest to restore the names and order of the entries
+    #[arg(long)]
+    manifest: bool,
+    /// Put every entry directly into the output directory, replacing the directory
+    /// separators in its name with `_`
+    #[arg(long)]
+    flatten: bool,
+    /// Only extract the entry with this name, can be given multiple times
+    #[arg(long = "file", value_name = "NAME")]
+    files: Vec<String>,
+    /// Only extract or list the entries whose name matches this glob pattern, e.g. `*.raw`,
+    /// can be given multiple times to match any of them
+    #[arg(long, value_name = "GLOB", conflicts_with = "files")]
+    filter: Vec<glob::Pattern>,
+    /// Write the entries selected with `--file` to stdout instead of the output directory
+    #[arg(
+        long,
+        requires = "files",
+        conflicts_with_all = ["out_dir", "list", "verify", "normalize_names", "manifest", "flatten"]
+    )]
+    stdout: bool,
+    /// Stop at the first entry that fails to extract or verify
+    #[arg(long)]
+    strict: bool,
+    /// Overwrite files that already exist in the output directory
+    #[arg(long)]
+    force: bool,
+    /// Don't show a progress bar while extracting
+    #[arg(long)]
+    quiet: bool,
+    /// Inflate entries whose data looks zlib compressed, copying all others as they are
+    #[arg(long)]
+    decompress: bool,
+}
+
+impl ExtractArgs {
+    /// Whether the entry named `name` passes the `--filter` patterns.
+    fn is_included(&self, name: &str) -> bool {
+        self.filter.is_empty() || self.filter.iter().any(|pattern| pattern.matches(name))
+    }
 }
 
 fn main() -> Result<()> {
     let cli = Cli::parse();
-    let file = File::open(cli.file)?;
-    let file = BufReader::new(file);
-    let mut hab = Hab::new(file)?;
 
-    let out_dir = cli.out_dir;
-    fs::create_dir_all(&out_dir)?;
+    init_logging(cli.verbose);
 
-    for i in 0..hab.num_entries() {
-        let mut hab_file = hab.get_file_by_index(i)?;
-        let mut out_file = File::create(out_dir.join(hab_file.file_name()))?;
+    match cli.command {
+        Some(Command::Pack {
+            dir,
+            out_file,
+            template,
+        }) => Ok(hab_tool::pack(&dir, &out_file, template.as_deref())?),
+        Some(Command::Verify { file, manifest }) => verify(&file, manifest.as_deref()),
+        Some(Command::Info { file }) => info(&file),
+        None => {
+            let args = cli
+                .extract
+                .expect("extraction arguments are required without a subcommand");
 
-        io::copy(&mut hab_file, &mut out_file)?;
+            extract(&args)
+        }
     }
+}
 
-    // eprintln!("{hab:#?}");
+/// Shows only warnings and errors by default, and more for every `-v`.
+fn init_logging(verbose: u8) {
+    let level = match verbose {
+        0 => Level::WARN,
+        1 => Level::INFO,
+        2 => Level::DEBUG,
+        _ => Level::TRACE,
+    };
 
-    Ok(())
+    tracing_subscriber::fmt()
+        .with_max_level(level)
+        .with_writer(io::stderr)
+        .with_ansi(io::stderr().is_terminal())
+        .with_target(false)
+        .without_time()
+        .init();
 }
 
-#[derive(Debug)]
-struct Hab<R> {
-    reader: R,
-    entries: Vec<FileEntry>,
-    total_size: u32,
-    data_start: u64,
+fn verify(path: &Path, manifest: Option<&Path>) -> Result<()> {
+    let source = Source::new(path)?;
+    let mut hab = Hab::new(source.open()?)?;
+    let check = hab.check_size()?;
+
+    println!(
+        "header says {} bytes, entries need {} bytes, archive has {} bytes",
+        hab.total_size, check.expected_size, check.actual_size
+    );
+
+    for problem in &check.problems {
+        warn!("{problem}");
+    }
+
+    ensure!(check.problems.is_empty(), "{path:?} looks corrupt");
+
+    if let Some(manifest) = manifest {
+        verify_digests(&mut hab, &Manifest::from_path(manifest)?)?;
+    }
+
+    Ok(())
 }
 
-impl<R> Hab<R>
+/// Compares the digest of every entry with the one recorded for the same index in `manifest`.
+fn verify_digests<R>(hab: &mut Hab<R>, manifest: &Manifest) -> Result<()>
 where
     R: BufRead + Seek,
 {
-    pub fn new(mut reader: R) -> Result<Self> {
-        reader.read_magic()?;
-        let _unknown = reader.read_bytes(16)?;
-        let num_entries = reader.read_u16()? as usize;
-        let _unknown = reader.read_u16()?;
-        let _unknown = reader.read_u32()?;
-        let total_size = reader.read_u32()?;
-        let mut file_metas = Vec::new();
+    let mut checked = 0;
+    let mut changed = 0;
+
+    if hab.num_entries() != manifest.entries.len() {
+        warn!(
+            "the archive has {} entries, but the manifest lists {}",
+            hab.num_entries(),
+            manifest.entries.len()
+        );
+        changed += 1;
+    }
+
+    for recorded in &manifest.entries {
+        let Some(expected) = &recorded.sha256 else {
+            continue;
+        };
+        let name = &recorded.entry.name;
 
-        for _ in 0..num_entries {
-            let entry = FileMeta::from_reader(&mut reader)?;
+        checked += 1;
 
-            file_metas.push(entry);
+        let Some(entry) = hab.entries.get(recorded.index) else {
+            warn!(
+                "entry {} ({name}) is missing from the archive",
+                recorded.index
+            );
+            changed += 1;
+            continue;
+        };
+
+        if entry.name != *name {
+            warn!(
+                "entry {} is named {} instead of {name}",
+                recorded.index, entry.name
+            );
+            changed += 1;
+            continue;
         }
 
-        // eprintln!("{:#?}", file_metas);
+        let hab_file = hab.get_file_by_index(recorded.index)?;
+        let mut data = entry_data(hab_file, recorded.decompressed)?;
+        let mut hasher = HashingWriter::new(io::sink());
+
+        io::copy(&mut data, &mut hasher).with_context(|| name.clone())?;
 
-        let filenames_start = reader.stream_position()?;
-        let mut entries = Vec::new();
+        let digest = hasher.hex_digest();
 
-        for meta in file_metas {
-            let mut name = Vec::new();
-            reader.seek(SeekFrom::Start(filenames_start + meta.name_offset))?;
-            reader.read_until(0, &mut name)?;
-            name.pop();
+        if digest != *expected {
+            warn!(
+                "entry {} ({name}) changed: expected {expected}, got {digest}",
+                recorded.index
+            );
+            changed += 1;
+        }
+    }
+
+    println!("checked {checked} digests, found {changed} differences");
+
+    ensure!(changed == 0, "the archive doesn't match the manifest");
+
+    Ok(())
+}
 
-            let name = String::from_utf8(name)?;
+fn info(path: &Path) -> Result<()> {
+    let source = Source::new(path)?;
+    let mut hab = Hab::new(source.open()?)?;
+    let check = hab.check_size()?;
+    let data_size = hab
+        .entries
+        .iter()
+        .map(|entry| entry.meta.data_size)
+        .sum::<u64>();
+    let size_note = if u64::from(hab.total_size) == check.actual_size {
+        "matches the archive".to_owned()
+    } else {
+        format!("but the archive has {} bytes", check.actual_size)
+    };
+    let (unk0, unk1, unk2) = hab.unknown_fields();
+    let unk0 = unk0
+        .iter()
+        .map(|byte| format!("{byte:02x}"))
+        .collect::<String>();
 
-            println!("filename: {name}");
+    println!("entries:    {}", hab.num_entries());
+    println!("data size:  {data_size} bytes");
 
-            entries.push(FileEntry { name, meta });
+    let largest = hab.entries.iter().max_by_key(|entry| entry.meta.data_size);
+    let smallest = hab.entries.iter().min_by_key(|entry| entry.meta.data_size);
+
+    if let (Some(largest), Some(smallest)) = (largest, smallest) {
+        println!(
+            "largest:    {} ({} bytes)",
+            largest.name, largest.meta.data_size
+        );
+        println!(
+            "smallest:   {} ({} bytes)",
+            smallest.name, smallest.meta.data_size
+        );
+    }
+
+    println!("data start: {:#x}", hab.data_start);
+    println!("total size: {} bytes, {size_note}", hab.total_size);
+    println!("unknown:    {unk0} {unk1:#06x} {unk2:#010x}");
+
+    Ok(())
+}
+
+fn extract(args: &ExtractArgs) -> Result<()> {
+    let archives = find_archives(&args.file)?;
+
+    if let [archive] = archives.as_slice()
+        && archive.subdir.is_none()
+    {
+        return extract_archive(args, &archive.path, args.out_dir.as_deref());
+    }
+
+    ensure!(
+        args.dump_meta.is_none() && args.verify.is_none() && !args.stdout,
+        "--dump-meta, --verify and --stdout only work with a single archive"
+    );
+
+    let mut failed = 0;
+
+    for archive in &archives {
+        let path = &archive.path;
+        let out_dir = args
+            .out_dir
+            .as_ref()
+            .zip(archive.subdir.as_ref())
+            .map(|(out_dir, subdir)| out_dir.join(subdir));
+
+        if args.list {
+            println!("{}:", path.display());
+        }
+
+        if let Err(err) = extract_archive(args, path, out_dir.as_deref()) {
+            error!("{path:?}: {err:?}");
+            failed += 1;
+
+            if args.strict {
+                warn!("stopping at the first error because of --strict");
+                break;
+            }
         }
+    }
+
+    info!("processed {} archives, {failed} failed", archives.len());
+
+    ensure!(failed == 0, "{failed} archives failed");
+
+    Ok(())
+}
+
+trait ArchiveData: BufRead + Seek + Send + Sync {}
+
+impl<R: BufRead + Seek + Send + Sync> ArchiveData for R {}
+
+/// Where the bytes of an archive are read from.
+enum Source {
+    File(PathBuf),
+    /// The whole archive read from stdin, as it has to be seekable.
+    Stdin(Vec<u8>),
+}
+
+impl Source {
+    /// `-` stands for stdin, anything else is a path.
+    fn new(path: &Path) -> Result<Self> {
+        if path != Path::new("-") {
+            return Ok(Self::File(path.to_owned()));
+        }
+
+        let mut data = Vec::new();
 
-        let data_start = reader.stream_position()?;
+        io::stdin()
+            .lock()
+            .read_to_end(&mut data)
+            .context("failed to read the archive from stdin")?;
 
-        Ok(Self {
-            reader,
-            total_size,
-            entries,
-            data_start,
+        Ok(Self::Stdin(data))
+    }
+
+    /// Opens a new reader for the archive, independent of any other reader opened before.
+    fn open(&self) -> Result<Box<dyn ArchiveData + '_>> {
+        match self {
+            Self::File(path) => {
+                let file = File::open(path).with_context(|| format!("{path:?}"))?;
+
+                Ok(Box::new(BufReader::new(file)))
+            }
+            Self::Stdin(data) => Ok(Box::new(Cursor::new(data.as_slice()))),
+        }
+    }
+}
+
+/// An archive to extract and the subdirectory of the output directory it goes into.
+struct Archive {
+    path: PathBuf,
+    subdir: Option<PathBuf>,
+}
+
+/// Resolves the input argument to the archives it refers to.
+///
+/// Directories are searched for `.hab` files, and anything that doesn't exist
+/// but looks like a glob pattern is expanded. The archives found that way are
+/// extracted into subdirectories named after their path relative to the directory,
+/// or the part of the pattern before the first wildcard.
+fn find_archives(input: &Path) -> Result<Vec<Archive>> {
+    let mut archives = Vec::new();
+
+    if input.is_dir() {
+        for entry in WalkDir::new(input).sort_by_file_name() {
+            let entry = entry?;
+            let path = entry.path();
+            let is_hab = path
+                .extension()
+                .is_some_and(|ext| ext.eq_ignore_ascii_case("hab"));
+
+            if entry.file_type().is_dir() || !is_hab {
+                continue;
+            }
+
+            archives.push(Archive {
+                path: path.to_owned(),
+                subdir: Some(path.strip_prefix(input)?.with_extension("")),
+            });
+        }
+    } else if let Some(pattern) = input
+        .to_str()
+        .filter(|pattern| !input.exists() && is_glob(pattern))
+    {
+        let base = input
+            .components()
+            .take_while(|component| !is_glob(&component.as_os_str().to_string_lossy()))
+            .collect::<PathBuf>();
+
+        for path in glob::glob(pattern)? {
+            let path = path?;
+
+            if path.is_dir() {
+                continue;
+            }
+
+            let subdir = path.strip_prefix(&base)?.with_extension("");
+
+            archives.push(Archive {
+                path,
+                subdir: Some(subdir),
+            });
+        }
+    } else {
+        archives.push(Archive {
+            path: input.to_owned(),
+            subdir: None,
+        });
+    }
+
+    ensure!(!archives.is_empty(), "{input:?} doesn't match any archives");
+
+    Ok(archives)
+}
+
+fn is_glob(pattern: &str) -> bool {
+    pattern.contains(['*', '?', '['])
+}
+
+fn extract_archive(args: &ExtractArgs, path: &Path, out_dir: Option<&Path>) -> Result<()> {
+    let source = Source::new(path)?;
+    let mut hab = Hab::new(source.open()?)?;
+
+    info!("{path:?} has {} entries", hab.num_entries());
+
+    if let Some(path) = &args.dump_meta {
+        dump_meta(&hab, path)?;
+    }
+
+    if args.list {
+        print_entries(&hab, args);
+        return Ok(());
+    }
+
+    for problem in hab.check_size()?.problems {
+        warn!("{problem}");
+    }
+
+    if args.stdout {
+        return extract_to_stdout(&mut hab, &args.files, args.decompress);
+    }
+
+    // Without an output directory only the metadata is dumped
+    let Some(out_dir) = out_dir else {
+        return Ok(());
+    };
+
+    fs::create_dir_all(out_dir)?;
+
+    let out_paths = output_paths(&hab, args);
+
+    if args.normalize_names {
+        save_normalized_names(&hab, &out_paths, out_dir)?;
+    }
+
+    ensure!(
+        !args.manifest || !out_paths.contains(&Some(PathBuf::from(Manifest::FILE_NAME))),
+        "an entry would be extracted to {}, which --manifest writes",
+        Manifest::FILE_NAME
+    );
+
+    let checksums = args.verify.as_deref().map(read_checksums).transpose()?;
+
+    // A name given twice would be extracted to the same path twice, in parallel
+    let mut files = Vec::new();
+
+    for name in &args.files {
+        if !files.contains(&name) {
+            files.push(name);
+        }
+    }
+
+    // Entries are extracted first and their diagnostics printed afterwards in entry order,
+    // so the output stays readable regardless of the order in which entries are processed.
+    let included = (0..hab.num_entries())
+        .filter(|&index| args.is_included(&hab.entries[index].name))
+        .collect::<Vec<_>>();
+    let selected = match files.is_empty() {
+        true => included.len(),
+        false => files.len(),
+    };
+    let total_size = (0..selected)
+        .filter_map(|position| match files.get(position) {
+            Some(name) => hab.entry_index(name),
+            None => Some(included[position]),
         })
+        .map(|index| hab.entries[index].meta.data_size)
+        .sum();
+    let progress = progress_bar(args.quiet, total_size);
+    let abort = AtomicBool::new(false);
+    let logs = (0..selected)
+        .into_par_iter()
+        .map(|position| {
+            if abort.load(Ordering::Relaxed) {
+                return None;
+            }
+
+            let index = match files.get(position) {
+                Some(name) => hab.find_entry(name).map_err(anyhow::Error::from),
+                None => Ok(included[position]),
+            };
+            let log = index
+                .and_then(|index| {
+                    let entry = &hab.entries[index];
+
+                    progress.set_message(entry.name.clone());
+
+                    let hab_file = hab.read_file_by_index(index, source.open()?)?;
+                    let relative_path = out_paths[index].as_deref();
+                    let log = extract_entry(
+                        hab_file,
+                        relative_path,
+                        out_dir,
+                        checksums.as_ref(),
+                        args.force,
+                        args.decompress,
+                    );
+
+                    progress.inc(entry.meta.data_size);
+
+                    log.map(|log| (Some(index), log))
+                })
+                .unwrap_or_else(|err| {
+                    let entry = match files.get(position) {
+                        Some(name) => format!("{name:?}"),
+                        None => format!("entry {}", included[position]),
+                    };
+                    let mut log = EntryLog::default();
+                    log.fail(format!("failed to extract {entry}: {err:?}"));
+                    (None, log)
+                });
+
+            if args.strict && log.1.failures > 0 {
+                abort.store(true, Ordering::Relaxed);
+            }
+
+            Some(log)
+        })
+        .collect::<Vec<_>>();
+
+    progress.finish_and_clear();
+
+    let mut manifest = args.manifest.then(|| Manifest::new(&hab, path, &out_paths));
+    let mut processed = 0;
+    let mut errors = 0;
+    let mut aborted = false;
+
+    for (index, mut log) in logs.into_iter().flatten() {
+        if let (Some(manifest), Some(index)) = (&mut manifest, index) {
+            let recorded = &mut manifest.entries[index];
+
+            recorded.sha256 = log.sha256.take();
+            recorded.decompressed = log.decompressed;
+        }
+
+        processed += 1;
+        errors += log.failures;
+        log.flush();
+
+        if args.strict && errors > 0 {
+            aborted = true;
+            break;
+        }
     }
 
-    fn num_entries(&self) -> usize {
-        self.entries.len()
+    trace!("{:#?}", hab.entries);
+
+    if let Some(checksums) = checksums
+        && !aborted
+    {
+        for name in checksums.keys() {
+            if !hab.entries.iter().any(|entry| &entry.name == name) {
+                error!("{name} is listed in the checksum file but missing from the archive");
+                errors += 1;
+            }
+        }
     }
 
-    fn get_file_by_index(&mut self, index: usize) -> Result<HabFile<R>> {
-        let entry = self.entries.get(index).context("invalid entry index")?;
+    if let Some(manifest) = manifest {
+        manifest.save(out_dir)?;
+    }
 
-        HabFile::new(&mut self.reader, entry, self.data_start)
+    if aborted {
+        warn!("stopping at the first error because of --strict");
     }
+
+    info!("processed {processed} files, {errors} errors");
+
+    ensure!(errors == 0, "{errors} errors occurred");
+
+    Ok(())
 }
 
-struct HabFile<'a, R> {
-    reader: io::Take<&'a mut R>,
-    entry: &'a FileEntry,
+/// Progress bar counting the extracted bytes, hidden with `--quiet` or if stderr
+/// isn't a terminal.
+fn progress_bar(quiet: bool, total_size: u64) -> ProgressBar {
+    if quiet || !io::stderr().is_terminal() {
+        return ProgressBar::hidden();
+    }
+
+    let style = ProgressStyle::with_template(
+        "{bar:40} {bytes}/{total_bytes} ({binary_bytes_per_sec}, {eta}) {wide_msg}",
+    )
+    .expect("progress bar template is valid");
+
+    ProgressBar::new(total_size).with_style(style)
 }
 
-impl<'a, R> HabFile<'a, R>
+/// Writes the entries named `names` to stdout, one after the other.
+fn extract_to_stdout<R>(hab: &mut Hab<R>, names: &[String], decompress: bool) -> Result<()>
 where
     R: BufRead + Seek,
 {
-    fn file_name(&self) -> &str {
-        &self.entry.name
-    }
+    let mut stdout = io::stdout().lock();
 
-    fn new(reader: &'a mut R, entry: &'a FileEntry, data_start: u64) -> Result<Self> {
-        reader.seek(SeekFrom::Start(data_start + entry.meta.data_offset))?;
+    for name in names {
+        let mut data = entry_data(hab.get_file_by_name(name)?, decompress)?;
+        let data_size = data.hab_file().entry().meta.data_size;
+        let copied = io::copy(&mut data, &mut stdout).with_context(|| name.clone())?;
 
-        Ok(Self {
-            reader: reader.take(entry.meta.data_size),
-            entry,
-        })
+        ensure!(
+            data.is_compressed() || copied == data_size,
+            "{name} is truncated, only {copied} of its {data_size} bytes are in the archive"
+        );
     }
+
+    stdout.flush()?;
+
+    Ok(())
 }
 
-impl<R> Read for HabFile<'_, R>
+/// Reads `hab_file` as it is stored, or decompressed if `decompress` is set and it looks compressed.
+fn entry_data<R>(hab_file: HabFile<'_, R>, decompress: bool) -> Result<EntryData<'_, R>>
 where
-    R: BufRead,
+    R: BufRead + Seek,
 {
-    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
-        self.reader.read(buf)
+    match decompress {
+        true => Ok(hab_file.decompressed()?),
+        false => Ok(EntryData::Stored(hab_file)),
     }
 }
 
-impl<R> BufRead for HabFile<'_, R>
+fn extract_entry<R>(
+    hab_file: HabFile<'_, R>,
+    relative_path: Option<&Path>,
+    out_dir: &Path,
+    checksums: Option<&HashMap<String, String>>,
+    force: bool,
+    decompress: bool,
+) -> Result<EntryLog>
 where
-    R: BufRead,
+    R: BufRead + Seek,
 {
-    fn fill_buf(&mut self) -> io::Result<&[u8]> {
-        self.reader.fill_buf()
+    let mut log = EntryLog::default();
+    let name = hab_file.file_name();
+    let out_path = relative_path.map(|path| out_dir.join(path));
+    let out_path = match out_path {
+        Some(out_path) if is_within(&out_path, out_dir)? => out_path,
+        _ => {
+            log.warn(format!(
+                "skipping {name:?} because it would be extracted outside of {out_dir:?}"
+            ));
+            return Ok(log);
+        }
+    };
+
+    if let Some(parent) = out_path.parent() {
+        fs::create_dir_all(parent).with_context(|| format!("{parent:?}"))?;
     }
 
-    fn consume(&mut self, amt: usize) {
-        self.reader.consume(amt);
+    log.debug(format!("extracting {name:?} to {out_path:?}"));
+
+    let is_fifo = is_fifo(&out_path);
+    let out_file = open_output(&out_path, is_fifo, force)?;
+    let mut out_file = HashingWriter::new(out_file);
+
+    let meta = &hab_file.entry().meta;
+    let data_size = meta.data_size;
+    let suspected_size = meta.suspected_uncompressed_size();
+    let mut data = entry_data(hab_file, decompress)?;
+
+    if data.is_compressed() {
+        log.debug(format!("decompressing {name:?}"));
     }
-}
 
-#[derive(Debug)]
-struct FileMeta {
-    name_offset: u64,
-    data_offset: u64,
-    data_size: u64,
-    // unknown: u32,
+    match io::copy(&mut data, &mut out_file) {
+        Ok(copied) if data.is_compressed() => {
+            if copied != suspected_size {
+                log.warn(format!(
+                    "{name} decompressed to {copied} bytes, but its entry suggests {suspected_size}"
+                ));
+            }
+        }
+        Ok(copied) if copied < data_size => {
+            log.fail(format!(
+                "{name} is truncated, only {copied} of its {data_size} bytes are in the archive"
+            ));
+        }
+        Ok(_) => {}
+        Err(err) if is_fifo && err.kind() == io::ErrorKind::BrokenPipe => {
+            log.warn(format!(
+                "reader of {out_path:?} went away, skipping the rest of {name}"
+            ));
+            return Ok(log);
+        }
+        Err(err) if data.is_compressed() => {
+            log.fail(format!(
+                "failed to decompress {name}, try again without --decompress: {err}"
+            ));
+        }
+        Err(err) => return Err(err).with_context(|| format!("{out_path:?}")),
+    }
+
+    let digest = out_file.hex_digest();
+
+    log.sha256 = Some(digest.clone());
+    log.decompressed = data.is_compressed();
+
+    let Some(checksums) = checksums else {
+        return Ok(log);
+    };
+
+    match checksums.get(name) {
+        Some(expected) if *expected == digest => {}
+        Some(expected) => {
+            log.fail(format!(
+                "checksum mismatch for {name}: expected {expected}, got {digest}"
+            ));
+        }
+        None => log.warn(format!("no checksum listed for {name}")),
+    }
+
+    Ok(log)
 }
 
-impl FileMeta {
-    fn from_reader<R>(r: &mut R) -> Result<Self>
-    where
-        R: BufRead + Seek,
-    {
-        let name_offset = r.read_u32()? as u64;
-        let data_offset = r.read_u32()? as u64;
-        let data_size = r.read_u32()? as u64;
-        let _unknown = r.read_u32()?;
-
-        Ok(Self {
-            name_offset,
-            data_offset,
-            data_size,
-        })
+fn print_entries<R>(hab: &Hab<R>, args: &ExtractArgs) {
+    println!("{:>5}  {:>10}  {:>10}  name", "index", "offset", "size");
+
+    for (index, entry) in hab.entries.iter().enumerate() {
+        if !args.is_included(&entry.name) {
+            continue;
+        }
+
+        println!(
+            "{index:>5}  {:>#10x}  {:>10}  {}",
+            entry.meta.data_offset, entry.meta.data_size, entry.name
+        );
     }
 }
 
-#[derive(Debug)]
-struct FileEntry {
-    name: String,
-    meta: FileMeta,
+fn dump_meta<R>(hab: &Hab<R>, path: &Path) -> Result<()> {
+    #[derive(Serialize)]
+    #[serde(bound = "")]
+    struct MetaDump<'a, R> {
+        num_entries: usize,
+        #[serde(flatten)]
+        hab: &'a Hab<R>,
+    }
+
+    let dump = MetaDump {
+        num_entries: hab.entries.len(),
+        hab,
+    };
+    let json = serde_json::to_string_pretty(&dump)?;
+
+    fs::write(path, json).with_context(|| format!("{path:?}"))?;
+
+    Ok(())
 }
 
-trait HabReader: Read + Seek {
-    fn read_magic(&mut self) -> Result<()> {
-        const MAGIC: &[u8] = b"HAB0";
+/// Paths of the files all entries are extracted to, relative to the output directory.
+///
+/// Entries that would end up at the same path get their index appended to tell
+/// them apart. Entries that can't be extracted safely are `None`.
+fn output_paths<R>(hab: &Hab<R>, args: &ExtractArgs) -> Vec<Option<PathBuf>> {
+    let mut paths = hab
+        .entries
+        .iter()
+        .map(|entry| output_path(args, &entry.name))
+        .collect::<Vec<_>>();
+    let mut collisions = BTreeMap::<PathBuf, Vec<usize>>::new();
 
-        let mut buf = [0; MAGIC.len()];
-        self.read_exact(&mut buf)?;
+    for (index, path) in paths.iter().enumerate() {
+        if let Some(path) = path {
+            collisions.entry(path.clone()).or_default().push(index);
+        }
+    }
+
+    for (path, indices) in collisions {
+        if indices.len() < 2 {
+            continue;
+        }
 
-        ensure!(buf == MAGIC);
+        warn!(
+            "entries {indices:?} would all be extracted to {path:?}, appending their index to the file names"
+        );
 
-        Ok(())
+        for index in indices {
+            let mut path = path.clone().into_os_string();
+
+            path.push(format!(".{index}"));
+            paths[index] = Some(path.into());
+        }
     }
 
-    fn read_bytes(&mut self, amount: usize) -> Result<Vec<u8>> {
-        let mut bytes = vec![0; amount];
+    paths
+}
 
-        self.read_exact(&mut bytes)?;
+/// Path of the file an entry is extracted to, relative to the output directory.
+///
+/// Entry names may contain `/` or `\` separators, which become subdirectories
+/// unless `--flatten` is given.
+///
+/// Returns `None` for names that can't be made safe, like ones containing `..`.
+fn output_path(args: &ExtractArgs, name: &str) -> Option<PathBuf> {
+    let components = name
+        .split(['/', '\\'])
+        .filter(|component| !component.is_empty() && *component != ".")
+        .collect::<Vec<_>>();
 
-        Ok(bytes)
+    if components.contains(&"..") {
+        return None;
     }
 
-    fn read_u32(&mut self) -> Result<u32> {
-        let mut buf = [0; 4];
+    let path = match args.flatten {
+        true => PathBuf::from(output_name(args, &components.join("_"))),
+        false => components
+            .iter()
+            .map(|component| output_name(args, component))
+            .collect(),
+    };
+
+    // Catches everything else that isn't a plain file name, like Windows drive prefixes
+    let is_relative = path
+        .components()
+        .all(|component| matches!(component, Component::Normal(_)));
+
+    (is_relative && path.components().next().is_some()).then_some(path)
+}
+
+/// Whether `path` stays inside of `dir` after resolving symlinks.
+///
+/// Only the part of `path` that already exists can be resolved, anything
+/// below it is created by the extraction itself.
+fn is_within(path: &Path, dir: &Path) -> Result<bool> {
+    let dir = dir.canonicalize().with_context(|| format!("{dir:?}"))?;
+    let Some(existing) = path.ancestors().find(|ancestor| ancestor.exists()) else {
+        return Ok(false);
+    };
+    let existing = existing
+        .canonicalize()
+        .with_context(|| format!("{existing:?}"))?;
 
-        self.read_exact(&mut buf)?;
+    Ok(existing.starts_with(dir))
+}
 
-        Ok(u32::from_le_bytes(buf))
+fn output_name(args: &ExtractArgs, name: &str) -> String {
+    if args.normalize_names {
+        normalize_name(name)
+    } else {
+        name.to_owned()
     }
+}
+
+/// Makes `name` usable as a file name on Windows and Unix systems.
+///
+/// Reserved and control characters are replaced with `_`, and overly long
+/// names are truncated.
+fn normalize_name(name: &str) -> String {
+    const MAX_LEN: usize = 200;
+    const RESERVED: &[char] = &['<', '>', ':', '"', '/', '\\', '|', '?', '*'];
+
+    let mut normalized = String::with_capacity(name.len());
 
-    fn read_u16(&mut self) -> Result<u16> {
-        let mut buf = [0; 2];
+    for c in name.chars() {
+        if normalized.len() + c.len_utf8() > MAX_LEN {
+            break;
+        }
 
-        self.read_exact(&mut buf)?;
+        if c.is_control() || RESERVED.contains(&c) {
+            normalized.push('_');
+        } else {
+            normalized.push(c);
+        }
+    }
 
-        Ok(u16::from_le_bytes(buf))
+    // Windows drops trailing dots and spaces
+    if normalized.ends_with(['.', ' ']) {
+        normalized.pop();
+        normalized.push('_');
     }
 
-    fn read_file_entry(&mut self) -> Result<FileMeta> {
-        let name_offset = self.read_u32()? as u64;
-        let data_offset = self.read_u32()? as u64;
-        let data_size = self.read_u32()? as u64;
-        let _unknown = self.read_u32()?;
+    if normalized.is_empty() {
+        normalized.push('_');
+    }
 
-        Ok(FileMeta {
-            name_offset,
-            data_offset,
-            data_size,
+    normalized
+}
+
+/// Writes the original names of all entries that are renamed by `--normalize-names`.
+fn save_normalized_names<R>(
+    hab: &Hab<R>,
+    out_paths: &[Option<PathBuf>],
+    out_dir: &Path,
+) -> Result<()> {
+    let renamed = hab
+        .entries
+        .iter()
+        .zip(out_paths)
+        .filter_map(|(entry, path)| {
+            let path = path.as_ref()?;
+
+            Some((entry.name.as_str(), path.to_string_lossy().into_owned()))
         })
+        .filter(|(name, normalized)| name != normalized)
+        .collect::<BTreeMap<_, _>>();
+
+    if renamed.is_empty() {
+        return Ok(());
+    }
+
+    let path = out_dir.join(NORMALIZED_NAMES_FILE_NAME);
+    let json = serde_json::to_string_pretty(&renamed)?;
+
+    fs::write(&path, json).with_context(|| format!("{path:?}"))?;
+
+    Ok(())
+}
+
+/// Opens the output file for an entry.
+///
+/// Named pipes are opened for writing as they are, so a consumer reading from
+/// the pipe receives the entry as it is being extracted. Opening blocks until
+/// the consumer opens its end.
+///
+/// Other existing files are only replaced with `force`.
+fn open_output(path: &Path, is_fifo: bool, force: bool) -> Result<File> {
+    let file = if is_fifo {
+        OpenOptions::new().write(true).open(path)
+    } else if force {
+        File::create(path)
+    } else {
+        OpenOptions::new().write(true).create_new(true).open(path)
+    };
+
+    match file {
+        Ok(file) => Ok(file),
+        Err(err) if err.kind() == io::ErrorKind::AlreadyExists => {
+            bail!("refusing to overwrite {path:?}, pass --force to replace it")
+        }
+        Err(err) => Err(err).with_context(|| format!("{path:?}")),
+    }
+}
+
+#[cfg(unix)]
+fn is_fifo(path: &Path) -> bool {
+    use std::os::unix::fs::FileTypeExt;
+
+    fs::metadata(path).is_ok_and(|metadata| metadata.file_type().is_fifo())
+}
+
+#[cfg(not(unix))]
+fn is_fifo(_path: &Path) -> bool {
+    false
+}
+
+/// Diagnostics and the digest collected while extracting a single entry.
+#[derive(Default)]
+struct EntryLog {
+    messages: Vec<(Level, String)>,
+    failures: usize,
+    /// Digest of the extracted file, for the manifest.
+    sha256: Option<String>,
+    decompressed: bool,
+}
+
+impl EntryLog {
+    fn debug(&mut self, message: String) {
+        self.messages.push((Level::DEBUG, message));
+    }
+
+    fn warn(&mut self, message: String) {
+        self.messages.push((Level::WARN, message));
+    }
+
+    fn fail(&mut self, message: String) {
+        self.messages.push((Level::ERROR, message));
+        self.failures += 1;
+    }
+
+    fn flush(self) {
+        for (level, message) in self.messages {
+            match level {
+                Level::ERROR => error!("{message}"),
+                Level::WARN => warn!("{message}"),
+                _ => debug!("{message}"),
+            }
+        }
+    }
+}
+
+/// Reads a checksum file in the format produced by `sha256sum`,
+/// mapping each file name to its lowercase hex digest.
+fn read_checksums(path: &Path) -> Result<HashMap<String, String>> {
+    let checksums = fs::read_to_string(path).with_context(|| format!("{path:?}"))?;
+    let mut map = HashMap::new();
+
+    for line in checksums.lines() {
+        if line.trim().is_empty() {
+            continue;
+        }
+
+        let (digest, name) = line
+            .split_once(' ')
+            .with_context(|| format!("malformed checksum line: {line:?}"))?;
+        let name = name.trim_start_matches([' ', '*']);
+
+        map.insert(name.to_owned(), digest.to_ascii_lowercase());
     }
+
+    Ok(map)
+}
+
+/// Writer that computes the SHA-256 of everything written through it.
+struct HashingWriter<W> {
+    inner: W,
+    hasher: Sha256,
 }
 
-impl<R: Read + Seek> HabReader for R {}
+impl<W> HashingWriter<W> {
+    fn new(inner: W) -> Self {
+        Self {
+            inner,
+            hasher: Sha256::new(),
+        }
+    }
+
+    fn hex_digest(self) -> String {
+        self.hasher
+            .finalize()
+            .iter()
+            .map(|byte| format!("{byte:02x}"))
+            .collect()
+    }
+}
+
+impl<W: Write> Write for HashingWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let written = self.inner.write(buf)?;
+
+        self.hasher.update(&buf[..written]);
+
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
@@ -0,0 +1,106 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use serde::{Deserialize, Serialize};
+
+use crate::{FileEntry, Hab, HabError, Result};
+
+/// Contents of the `manifest.json` an archive can be extracted with, describing
+/// where the extracted files came from and every entry in archive order.
+#[derive(Serialize, Deserialize)]
+pub struct Manifest {
+    /// Path of the archive the files were extracted from, `-` for stdin.
+    pub source: PathBuf,
+    /// When the files were extracted, in seconds since the Unix epoch.
+    pub extracted_at: u64,
+    /// Version of the tool that extracted the files.
+    pub tool_version: String,
+    pub total_size: u32,
+    pub data_start: u64,
+    pub entries: Vec<ManifestEntry>,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct ManifestEntry {
+    pub index: usize,
+    #[serde(flatten)]
+    pub entry: FileEntry,
+    /// Relative to the output directory, `None` for entries that weren't extracted.
+    pub path: Option<PathBuf>,
+    /// Hex SHA-256 digest of the extracted file.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub sha256: Option<String>,
+    /// Whether the entry was inflated with `--decompress`, so `sha256` is the
+    /// digest of its decompressed data.
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub decompressed: bool,
+}
+
+impl Manifest {
+    pub const FILE_NAME: &str = "manifest.json";
+
+    /// Describes the entries of `hab`, read from `source`, being extracted to `paths`.
+    pub fn new<R>(hab: &Hab<R>, source: &Path, paths: &[Option<PathBuf>]) -> Self {
+        let entries = hab
+            .entries
+            .iter()
+            .zip(paths)
+            .enumerate()
+            .map(|(index, (entry, path))| ManifestEntry {
+                index,
+                entry: entry.clone(),
+                path: path.clone(),
+                sha256: None,
+                decompressed: false,
+            })
+            .collect();
+        let extracted_at = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |elapsed| elapsed.as_secs());
+
+        Self {
+            source: source.to_owned(),
+            extracted_at,
+            tool_version: env!("CARGO_PKG_VERSION").to_owned(),
+            total_size: hab.total_size,
+            data_start: hab.data_start,
+            entries,
+        }
+    }
+
+    /// Reads the manifest at `path`.
+    pub fn from_path(path: &Path) -> Result<Self> {
+        let json = fs::read(path).map_err(HabError::file(path))?;
+        let manifest = serde_json::from_slice(&json).map_err(HabError::json(path))?;
+
+        Ok(manifest)
+    }
+
+    /// Reads the manifest of the files extracted to `dir`, if there is one.
+    pub fn load(dir: &Path) -> Result<Option<Self>> {
+        let path = dir.join(Self::FILE_NAME);
+
+        if !path.exists() {
+            return Ok(None);
+        }
+
+        Self::from_path(&path).map(Some)
+    }
+
+    pub fn save(&self, dir: &Path) -> Result<()> {
+        let path = dir.join(Self::FILE_NAME);
+        let json = serde_json::to_string_pretty(self).map_err(HabError::json(&path))?;
+
+        fs::write(&path, json).map_err(HabError::file(&path))?;
+
+        Ok(())
+    }
+
+    /// The entry that was extracted to `path`, relative to the output directory.
+    pub fn entry_at(&self, path: &Path) -> Option<&ManifestEntry> {
+        self.entries
+            .iter()
+            .find(|entry| entry.path.as_deref() == Some(path))
+    }
+}
@@ -0,0 +1,189 @@
+use std::collections::BTreeMap;
+use std::fs::{self, File};
+use std::io::{self, BufReader, BufWriter, Write};
+use std::path::{Path, PathBuf};
+
+use walkdir::WalkDir;
+
+use crate::{FileMeta, Hab, HabError, Manifest, Result};
+
+/// Size of the fixed part of the archive header, before the entry table.
+const HEADER_SIZE: u64 = 32;
+/// Size of a single `FileMeta` in the entry table.
+const FILE_META_SIZE: u64 = 16;
+/// File mapping the original names of entries renamed by `--normalize-names` to their paths.
+pub const NORMALIZED_NAMES_FILE_NAME: &str = "normalized_names.json";
+
+/// Writes the files below `dir` into a new archive at `out_file`.
+///
+/// Files in subdirectories are stored under their relative path, using `/` as separator.
+///
+/// Files that also exist in `template` are stored in the template's order
+/// and with its unknown fields, everything else follows sorted by name with
+/// the unknown fields zeroed.
+///
+/// If `dir` has a `manifest.json`, files listed in it get back the name, order
+/// and unknown field of the entry they were extracted from, unless `template`
+/// has them. Otherwise files renamed by `--normalize-names` get back their
+/// original name. Neither the manifest nor the renames are packed.
+pub fn pack(dir: &Path, out_file: &Path, template: Option<&Path>) -> Result<()> {
+    let template = template
+        .map(|path| {
+            let file = File::open(path).map_err(HabError::file(path))?;
+
+            Hab::new(BufReader::new(file)).map_err(|err| HabError::Template {
+                path: path.to_owned(),
+                source: Box::new(err),
+            })
+        })
+        .transpose()?;
+
+    let manifest = Manifest::load(dir)?;
+    let original_names = load_original_names(dir)?;
+    // Archive names and the files they are read from, relative to `dir`
+    let mut files = Vec::new();
+
+    for entry in WalkDir::new(dir) {
+        let entry = entry.map_err(|err| HabError::File {
+            path: err.path().unwrap_or(dir).to_owned(),
+            source: err.into(),
+        })?;
+
+        if !entry.file_type().is_file() {
+            continue;
+        }
+
+        let path = entry
+            .path()
+            .strip_prefix(dir)
+            .expect("WalkDir only yields paths below dir");
+
+        if path == Path::new(Manifest::FILE_NAME) || path == Path::new(NORMALIZED_NAMES_FILE_NAME) {
+            continue;
+        }
+
+        let extracted = manifest
+            .as_ref()
+            .and_then(|manifest| manifest.entry_at(path));
+        let name = match (extracted, original_names.get(path)) {
+            (Some(extracted), _) => extracted.entry.name.clone(),
+            (None, Some(original)) => original.clone(),
+            (None, None) => path
+                .components()
+                .map(|component| component.as_os_str().to_str())
+                .collect::<Option<Vec<_>>>()
+                .ok_or_else(|| HabError::PathNotUtf8(path.to_owned()))?
+                .join("/"),
+        };
+
+        files.push((name, path.to_owned()));
+    }
+
+    files.sort();
+
+    if let Some(manifest) = &manifest {
+        files.sort_by_key(|(_, path)| {
+            manifest
+                .entry_at(path)
+                .map_or(usize::MAX, |extracted| extracted.index)
+        });
+    }
+
+    if let Some(template) = &template {
+        files.sort_by_key(|(name, _)| template.entry_index(name).unwrap_or(usize::MAX));
+    }
+
+    let num_entries = u16::try_from(files.len()).map_err(|_| HabError::TooManyEntries)?;
+    let mut metas = Vec::new();
+    let mut name_block = Vec::new();
+    let mut data_size = 0;
+
+    for (name, relative_path) in &files {
+        let path = dir.join(relative_path);
+        let size = fs::metadata(&path).map_err(HabError::file(&path))?.len();
+        let from_template = template.as_ref().and_then(|template| {
+            template
+                .entry_index(name)
+                .map(|index| &template.entries[index])
+        });
+        let from_manifest = manifest
+            .as_ref()
+            .and_then(|manifest| manifest.entry_at(relative_path))
+            .map(|extracted| &extracted.entry);
+        let unknown = from_template
+            .or(from_manifest)
+            .map_or(0, |entry| entry.meta._unk0);
+
+        metas.push(FileMeta {
+            name_offset: name_block.len() as u64,
+            data_offset: data_size,
+            data_size: size,
+            _unk0: unknown,
+        });
+
+        name_block.extend_from_slice(name.as_bytes());
+        name_block.push(0);
+        data_size += size;
+    }
+
+    let total_size =
+        HEADER_SIZE + FILE_META_SIZE * metas.len() as u64 + name_block.len() as u64 + data_size;
+    let total_size = u32::try_from(total_size).map_err(|_| HabError::TooLarge)?;
+
+    let (unk0, unk1, unk2) = match &template {
+        Some(template) => (template._unk0.clone(), template._unk1, template._unk2),
+        None => (vec![0; 16], 0, 0),
+    };
+
+    // Everything in front of the data is small enough to be assembled in memory
+    let mut header = b"HAB0".to_vec();
+
+    header.extend_from_slice(&unk0);
+    header.extend_from_slice(&num_entries.to_le_bytes());
+    header.extend_from_slice(&unk1.to_le_bytes());
+    header.extend_from_slice(&unk2.to_le_bytes());
+    header.extend_from_slice(&total_size.to_le_bytes());
+
+    for meta in &metas {
+        meta.write_to(&mut header)?;
+    }
+
+    header.extend_from_slice(&name_block);
+
+    let file = File::create(out_file).map_err(HabError::file(out_file))?;
+    let mut out = BufWriter::new(file);
+
+    out.write_all(&header).map_err(HabError::file(out_file))?;
+
+    for ((_, relative_path), meta) in files.iter().zip(&metas) {
+        let path = dir.join(relative_path);
+        let mut file = File::open(&path).map_err(HabError::file(&path))?;
+        let copied = io::copy(&mut file, &mut out).map_err(HabError::file(&path))?;
+
+        if copied != meta.data_size {
+            return Err(HabError::SizeChanged(path));
+        }
+    }
+
+    out.flush().map_err(HabError::file(out_file))?;
+
+    Ok(())
+}
+
+/// Reads the renames recorded by `--normalize-names`, keyed by the path the entry was written to.
+fn load_original_names(dir: &Path) -> Result<BTreeMap<PathBuf, String>> {
+    let path = dir.join(NORMALIZED_NAMES_FILE_NAME);
+
+    if !path.exists() {
+        return Ok(BTreeMap::new());
+    }
+
+    let json = fs::read(&path).map_err(HabError::file(&path))?;
+    let renamed = serde_json::from_slice::<BTreeMap<String, PathBuf>>(&json)
+        .map_err(HabError::json(&path))?;
+
+    Ok(renamed
+        .into_iter()
+        .map(|(original, normalized)| (normalized, original))
+        .collect())
+}
@@ -0,0 +1,61 @@
+// Every test binary only uses some of the helpers
+#![allow(dead_code)]
+
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+pub fn hab_tool(args: &[&Path]) {
+    assert!(hab_tool_succeeds(args), "hab_tool {args:?} failed");
+}
+
+/// Runs hab_tool with its output captured, returning whether it succeeded.
+pub fn hab_tool_succeeds(args: &[&Path]) -> bool {
+    Command::new(env!("CARGO_BIN_EXE_hab_tool"))
+        .args(args)
+        .output()
+        .unwrap()
+        .status
+        .success()
+}
+
+pub fn temp_dir(name: &str) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("hab_tool_{name}_{}", std::process::id()));
+
+    let _ = fs::remove_dir_all(&dir);
+    fs::create_dir_all(&dir).unwrap();
+
+    dir
+}
+
+/// Builds an archive the way the game's archives are laid out.
+pub fn build_archive(files: &[(&str, &[u8], u32)]) -> Vec<u8> {
+    let mut metas = Vec::new();
+    let mut names = Vec::new();
+    let mut data = Vec::new();
+
+    for (name, contents, unknown) in files {
+        for field in [names.len(), data.len(), contents.len()] {
+            metas.extend_from_slice(&(field as u32).to_le_bytes());
+        }
+
+        metas.extend_from_slice(&unknown.to_le_bytes());
+        names.extend_from_slice(name.as_bytes());
+        names.push(0);
+        data.extend_from_slice(contents);
+    }
+
+    let total_size = 32 + metas.len() + names.len() + data.len();
+    let mut archive = b"HAB0".to_vec();
+
+    archive.extend((1..=16).collect::<Vec<u8>>());
+    archive.extend_from_slice(&(files.len() as u16).to_le_bytes());
+    archive.extend_from_slice(&0xbeef_u16.to_le_bytes());
+    archive.extend_from_slice(&0xdead_beef_u32.to_le_bytes());
+    archive.extend_from_slice(&(total_size as u32).to_le_bytes());
+    archive.extend(metas);
+    archive.extend(names);
+    archive.extend(data);
+
+    archive
+}
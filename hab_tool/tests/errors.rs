@@ -0,0 +1,147 @@
+use std::fs;
+use std::io::Cursor;
+
+use common::{build_archive, temp_dir};
+use hab_tool::{Hab, HabError, Manifest};
+
+mod common;
+
+fn open(archive: &[u8]) -> Result<Hab<Cursor<&[u8]>>, HabError> {
+    Hab::new(Cursor::new(archive))
+}
+
+#[test]
+fn archives_are_told_apart_by_magic_and_version() {
+    let mut archive = build_archive(&[("a.txt", b"a", 0)]);
+
+    archive[3] = b'1';
+    assert!(matches!(
+        open(&archive),
+        Err(HabError::UnsupportedVersion(b'1'))
+    ));
+
+    archive[0] = b'X';
+    assert!(matches!(open(&archive), Err(HabError::BadMagic { found }) if found == b"XAB1"));
+}
+
+#[test]
+fn archives_ending_inside_the_entry_table_are_truncated() {
+    let archive = build_archive(&[("a.txt", b"a", 0), ("b.txt", b"b", 0)]);
+
+    assert!(matches!(open(&archive[..40]), Err(HabError::Truncated)));
+}
+
+#[test]
+fn names_that_are_not_utf8_are_reported_with_their_index() {
+    let mut archive = build_archive(&[("a.txt", b"a", 0), ("b.txt", b"b", 0)]);
+    // The names follow the 32 byte header and the two 16 byte entries
+    let name_start = 32 + 2 * 16 + "a.txt\0".len();
+
+    archive[name_start] = 0xff;
+
+    assert!(matches!(
+        open(&archive),
+        Err(HabError::NameNotUtf8 { index: 1 })
+    ));
+}
+
+#[test]
+fn name_offsets_outside_of_the_names_are_rejected() {
+    let archive = build_archive(&[("a.txt", b"a", 0), ("b.txt", b"data\0", 0)]);
+    // The name offset is the first field of an entry, after the 32 byte header
+    let with_name_offset = |index: usize, offset: u32| {
+        let mut archive = archive.clone();
+        let field = 32 + index * 16;
+
+        archive[field..field + 4].copy_from_slice(&offset.to_le_bytes());
+        archive
+    };
+
+    // Past the end of the archive
+    assert!(matches!(
+        open(&with_name_offset(0, 0x1000)),
+        Err(HabError::NameOutOfBounds {
+            index: 0,
+            offset: 0x1000
+        })
+    ));
+
+    // Into the data, which follows the names "a.txt" and "b.txt"
+    assert!(matches!(
+        open(&with_name_offset(0, 13)),
+        Err(HabError::NameOutOfBounds {
+            index: 0,
+            offset: 13
+        })
+    ));
+
+    // The same for the last entry, whose name is read last
+    assert!(matches!(
+        open(&with_name_offset(1, 13)),
+        Err(HabError::NameOutOfBounds {
+            index: 1,
+            offset: 13
+        })
+    ));
+}
+
+#[test]
+fn names_of_padded_archives_are_accepted() {
+    let archive = build_archive(&[("a.txt", b"a", 0), ("b.txt", b"data", 0)]);
+    let padded = [&archive[..], &[0; 11]].concat();
+    // The total size is the last field of the 32 byte header
+    let mut padded_and_recorded = padded.clone();
+
+    padded_and_recorded[28..32].copy_from_slice(&(padded.len() as u32).to_le_bytes());
+
+    for archive in [padded, padded_and_recorded] {
+        let mut hab = open(&archive).unwrap();
+        let files = hab.read_all().unwrap();
+
+        assert_eq!(files["a.txt"], b"a");
+        assert_eq!(files["b.txt"], b"data");
+    }
+}
+
+#[test]
+fn unknown_names_come_with_close_matches() {
+    let archive = build_archive(&[("model.bin", b"", 0), ("texture.raw", b"", 0)]);
+    let hab = open(&archive).unwrap();
+
+    match hab.find_entry("modle.bin") {
+        Err(HabError::NoSuchEntry { close_matches, .. }) => {
+            assert_eq!(close_matches, ["model.bin"]);
+        }
+        other => panic!("expected NoSuchEntry, got {other:?}"),
+    }
+
+    assert!(matches!(
+        hab.read_file_by_index(2, Cursor::new(&archive[..])),
+        Err(HabError::InvalidEntryIndex(2))
+    ));
+}
+
+#[test]
+fn packing_reports_which_file_failed() {
+    let dir = temp_dir("pack_errors");
+    let (files, out_file, template) = (dir.join("files"), dir.join("out.hab"), dir.join("t.hab"));
+
+    fs::create_dir_all(&files).unwrap();
+    fs::write(files.join("a.txt"), "a").unwrap();
+    fs::write(&template, "nope").unwrap();
+
+    assert!(matches!(
+        hab_tool::pack(&files, &out_file, Some(&template)),
+        Err(HabError::Template { path, source })
+            if path == template && matches!(*source, HabError::BadMagic { .. })
+    ));
+
+    fs::write(files.join(Manifest::FILE_NAME), "{").unwrap();
+
+    assert!(matches!(
+        hab_tool::pack(&files, &out_file, None),
+        Err(HabError::Json { path, .. }) if path == files.join(Manifest::FILE_NAME)
+    ));
+
+    fs::remove_dir_all(&dir).unwrap();
+}
@@ -0,0 +1,116 @@
+use std::fs;
+use std::io::Write;
+use std::path::Path;
+
+use common::{build_archive, hab_tool, temp_dir};
+use flate2::Compression;
+use flate2::write::ZlibEncoder;
+
+mod common;
+
+#[test]
+fn duplicate_names_are_both_extracted() {
+    let dir = temp_dir("duplicates");
+    let archive_path = dir.join("duplicates.hab");
+    let out_dir = dir.join("extracted");
+
+    let archive = build_archive(&[
+        ("same.txt", b"first\n", 0),
+        ("other.txt", b"other\n", 0),
+        ("same.txt", b"second\n", 0),
+    ]);
+
+    fs::write(&archive_path, archive).unwrap();
+
+    hab_tool(&[&archive_path, &out_dir]);
+
+    assert_eq!(fs::read(out_dir.join("same.txt.0")).unwrap(), b"first\n");
+    assert_eq!(fs::read(out_dir.join("other.txt")).unwrap(), b"other\n");
+    assert_eq!(fs::read(out_dir.join("same.txt.2")).unwrap(), b"second\n");
+    assert!(!out_dir.join("same.txt").exists());
+
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn files_given_twice_are_extracted_once() {
+    let dir = temp_dir("file_twice");
+    let archive_path = dir.join("file_twice.hab");
+    let out_dir = dir.join("extracted");
+
+    let archive = build_archive(&[("a.txt", b"a\n", 0), ("b.txt", b"b\n", 0)]);
+
+    fs::write(&archive_path, archive).unwrap();
+
+    hab_tool(&[
+        &archive_path,
+        &out_dir,
+        Path::new("--file"),
+        Path::new("a.txt"),
+        Path::new("--file"),
+        Path::new("a.txt"),
+    ]);
+
+    assert_eq!(fs::read(out_dir.join("a.txt")).unwrap(), b"a\n");
+    assert!(!out_dir.join("b.txt").exists());
+
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn filters_select_entries_matching_any_pattern() {
+    let dir = temp_dir("filter");
+    let archive_path = dir.join("filter.hab");
+    let out_dir = dir.join("extracted");
+
+    let archive = build_archive(&[
+        ("textures/a.raw", b"raw\n", 0),
+        ("textures/a.header", b"header\n", 0),
+        ("sounds/a.wav", b"wav\n", 0),
+        ("script.lua", b"lua\n", 0),
+    ]);
+
+    fs::write(&archive_path, archive).unwrap();
+
+    hab_tool(&[
+        &archive_path,
+        &out_dir,
+        Path::new("--filter"),
+        Path::new("*.raw"),
+        Path::new("--filter"),
+        Path::new("*.header"),
+    ]);
+
+    assert!(out_dir.join("textures/a.raw").exists());
+    assert!(out_dir.join("textures/a.header").exists());
+    assert!(!out_dir.join("sounds").exists());
+    assert!(!out_dir.join("script.lua").exists());
+
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn decompress_inflates_only_compressed_entries() {
+    let dir = temp_dir("decompress");
+    let archive_path = dir.join("decompress.hab");
+    let out_dir = dir.join("extracted");
+
+    let contents = b"compressed contents\n".repeat(10);
+    let mut encoder = ZlibEncoder::new(Vec::new(), Compression::default());
+    encoder.write_all(&contents).unwrap();
+    let compressed = encoder.finish().unwrap();
+
+    let archive = build_archive(&[
+        ("compressed.bin", &compressed, contents.len() as u32),
+        ("stored.txt", b"stored\n", 0),
+    ]);
+
+    fs::write(&archive_path, archive).unwrap();
+
+    hab_tool(&[&archive_path, &out_dir, Path::new("--decompress")]);
+
+    assert_eq!(fs::read(out_dir.join("compressed.bin")).unwrap(), contents);
+    assert_eq!(fs::read(out_dir.join("stored.txt")).unwrap(), b"stored\n");
+
+    fs::remove_dir_all(&dir).unwrap();
+}
@@ -0,0 +1,124 @@
+use std::fs;
+use std::io::Cursor;
+use std::path::Path;
+
+use common::{build_archive, hab_tool, temp_dir};
+use hab_tool::Hab;
+
+mod common;
+
+#[test]
+fn packing_an_extracted_archive_reproduces_it() {
+    let dir = temp_dir("roundtrip");
+    let original_path = dir.join("original.hab");
+    let extracted_dir = dir.join("extracted");
+    let packed_path = dir.join("packed.hab");
+
+    // Names deliberately not in alphabetical order, so the template's order matters
+    let original = build_archive(&[
+        ("model.bin", &[0, 1, 2, 3, 255], 7),
+        ("empty.dat", &[], 0),
+        ("a.txt", b"hello\n", 0x1234_5678),
+    ]);
+
+    fs::write(&original_path, &original).unwrap();
+
+    hab_tool(&[&original_path, &extracted_dir]);
+    hab_tool(&[
+        Path::new("pack"),
+        &extracted_dir,
+        &packed_path,
+        Path::new("--template"),
+        &original_path,
+    ]);
+
+    assert_eq!(fs::read(&packed_path).unwrap(), original);
+
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn packing_with_a_manifest_restores_names_and_order() {
+    let dir = temp_dir("manifest");
+    let original_path = dir.join("original.hab");
+    let extracted_dir = dir.join("extracted");
+    let packed_path = dir.join("packed.hab");
+
+    fs::write(
+        &original_path,
+        build_archive(&[
+            ("textures/b.raw", b"b", 1),
+            ("same.txt", b"first\n", 2),
+            ("textures/a.raw", b"a", 3),
+            ("same.txt", b"second\n", 4),
+        ]),
+    )
+    .unwrap();
+
+    // Flattened and with duplicates renamed, so the names only survive through the manifest
+    hab_tool(&[
+        &original_path,
+        &extracted_dir,
+        Path::new("--manifest"),
+        Path::new("--flatten"),
+    ]);
+    hab_tool(&[Path::new("pack"), &extracted_dir, &packed_path]);
+
+    let packed = Hab::new(Cursor::new(fs::read(&packed_path).unwrap())).unwrap();
+    let entries = packed
+        .entries
+        .iter()
+        .map(|entry| {
+            (
+                entry.name.as_str(),
+                entry.meta.data_size,
+                entry.meta.suspected_uncompressed_size(),
+            )
+        })
+        .collect::<Vec<_>>();
+
+    assert_eq!(
+        entries,
+        [
+            ("textures/b.raw", 1, 1),
+            ("same.txt", 6, 2),
+            ("textures/a.raw", 1, 3),
+            ("same.txt", 7, 4),
+        ]
+    );
+
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn packing_restores_normalized_names() {
+    let dir = temp_dir("normalized");
+    let original_path = dir.join("original.hab");
+    let extracted_dir = dir.join("extracted");
+    let packed_path = dir.join("packed.hab");
+
+    fs::write(
+        &original_path,
+        build_archive(&[("a:b?.txt", b"renamed", 0), ("plain.txt", b"kept", 0)]),
+    )
+    .unwrap();
+
+    hab_tool(&[
+        &original_path,
+        &extracted_dir,
+        Path::new("--normalize-names"),
+    ]);
+    hab_tool(&[Path::new("pack"), &extracted_dir, &packed_path]);
+
+    let packed = Hab::new(Cursor::new(fs::read(&packed_path).unwrap())).unwrap();
+    let names = packed
+        .entries
+        .iter()
+        .map(|entry| entry.name.as_str())
+        .collect::<Vec<_>>();
+
+    assert!(extracted_dir.join("normalized_names.json").exists());
+    assert_eq!(names, ["a:b?.txt", "plain.txt"]);
+
+    fs::remove_dir_all(&dir).unwrap();
+}
@@ -0,0 +1,32 @@
+use std::io::Cursor;
+
+use common::build_archive;
+use hab_tool::{Hab, HabError};
+
+mod common;
+
+#[test]
+fn read_all_maps_names_to_contents() {
+    let archive = build_archive(&[
+        ("same.txt", b"first\n", 0),
+        ("other.txt", b"other\n", 0),
+        ("same.txt", b"second\n", 0),
+    ]);
+    let files = Hab::new(Cursor::new(archive)).unwrap().read_all().unwrap();
+
+    assert_eq!(files.len(), 3);
+    assert_eq!(files["same.txt.0"], b"first\n");
+    assert_eq!(files["other.txt"], b"other\n");
+    assert_eq!(files["same.txt.2"], b"second\n");
+}
+
+#[test]
+fn read_all_fails_on_truncated_entries() {
+    let mut archive = build_archive(&[("a.txt", b"hello\n", 0)]);
+
+    archive.truncate(archive.len() - 1);
+
+    let mut hab = Hab::new(Cursor::new(archive)).unwrap();
+
+    assert!(matches!(hab.read_all(), Err(HabError::Truncated)));
+}
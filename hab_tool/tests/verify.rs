@@ -0,0 +1,42 @@
+use std::fs;
+use std::path::Path;
+
+use common::{build_archive, hab_tool, hab_tool_succeeds, temp_dir};
+
+mod common;
+
+#[test]
+fn repacked_archives_are_verified_against_the_manifest() {
+    let dir = temp_dir("verify");
+    let original_path = dir.join("original.hab");
+    let extracted_dir = dir.join("extracted");
+    let manifest_path = extracted_dir.join("manifest.json");
+    let packed_path = dir.join("packed.hab");
+
+    fs::write(
+        &original_path,
+        build_archive(&[("a.txt", b"hello\n", 0), ("b.bin", &[1, 2, 3], 0)]),
+    )
+    .unwrap();
+
+    hab_tool(&[&original_path, &extracted_dir, Path::new("--manifest")]);
+    hab_tool(&[Path::new("pack"), &extracted_dir, &packed_path]);
+
+    let verify = [
+        Path::new("verify"),
+        &packed_path,
+        Path::new("--manifest"),
+        &manifest_path,
+    ];
+
+    assert!(hab_tool_succeeds(&verify));
+
+    // Change the last byte of b.bin
+    let mut packed = fs::read(&packed_path).unwrap();
+    *packed.last_mut().unwrap() ^= 0xff;
+    fs::write(&packed_path, packed).unwrap();
+
+    assert!(!hab_tool_succeeds(&verify));
+
+    fs::remove_dir_all(&dir).unwrap();
+}
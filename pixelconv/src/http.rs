@@ -0,0 +1,106 @@
+use std::io::{self, Read, Seek, SeekFrom};
+
+use anyhow::{Context, Result, bail};
+use reqwest::StatusCode;
+use reqwest::blocking::Client;
+use reqwest::header::{CONTENT_LENGTH, RANGE};
+
+pub fn is_url(s: &str) -> bool {
+    s.starts_with("http://") || s.starts_with("https://")
+}
+
+/// Whether `err` is a request that failed with 404 Not Found.
+pub fn is_not_found(err: &anyhow::Error) -> bool {
+    err.downcast_ref::<reqwest::Error>()
+        .is_some_and(|err| err.status() == Some(StatusCode::NOT_FOUND))
+}
+
+/// Seekable reader over a remote file that fetches only the requested bytes
+/// using HTTP range requests.
+///
+/// Every read issues a request, so this should be wrapped in a `BufReader`.
+pub struct HttpReader {
+    client: Client,
+    url: String,
+    len: u64,
+    pos: u64,
+}
+
+impl HttpReader {
+    pub fn open(url: &str) -> Result<Self> {
+        let client = Client::new();
+        let response = client
+            .head(url)
+            .send()
+            .and_then(|response| response.error_for_status())
+            .with_context(|| format!("HEAD {url}"))?;
+        let len = response
+            .headers()
+            .get(CONTENT_LENGTH)
+            .and_then(|len| len.to_str().ok())
+            .and_then(|len| len.parse().ok())
+            .with_context(|| format!("{url} has no known length"))?;
+
+        Ok(Self {
+            client,
+            url: url.to_owned(),
+            len,
+            pos: 0,
+        })
+    }
+
+    fn read_range(&self, buf: &mut [u8]) -> Result<usize> {
+        let end = (self.pos + buf.len() as u64).min(self.len);
+        let response = self
+            .client
+            .get(&self.url)
+            .header(RANGE, format!("bytes={}-{}", self.pos, end - 1))
+            .send()
+            .and_then(|response| response.error_for_status())?;
+
+        if response.status() != StatusCode::PARTIAL_CONTENT {
+            bail!("{} does not support range requests", self.url);
+        }
+
+        let mut body = response.take(end - self.pos);
+        let mut read = 0;
+
+        loop {
+            match body.read(&mut buf[read..])? {
+                0 => break,
+                n => read += n,
+            }
+        }
+
+        Ok(read)
+    }
+}
+
+impl Read for HttpReader {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if buf.is_empty() || self.pos >= self.len {
+            return Ok(0);
+        }
+
+        let read = self.read_range(buf).map_err(io::Error::other)?;
+        self.pos += read as u64;
+
+        Ok(read)
+    }
+}
+
+impl Seek for HttpReader {
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        let pos = match pos {
+            SeekFrom::Start(pos) => Some(pos),
+            SeekFrom::End(offset) => self.len.checked_add_signed(offset),
+            SeekFrom::Current(offset) => self.pos.checked_add_signed(offset),
+        };
+
+        self.pos = pos.ok_or_else(|| {
+            io::Error::new(io::ErrorKind::InvalidInput, "seek to a negative position")
+        })?;
+
+        Ok(self.pos)
+    }
+}
@@ -0,0 +1,1104 @@
+//! Parsing of bank headers and decoding of the textures stored in raw files.
+
+use std::fs;
+use std::io::{BufRead, Cursor, Read, Seek, SeekFrom, Write};
+use std::ops::{Range, RangeInclusive};
+use std::path::Path;
+use std::str::FromStr;
+
+use anyhow::{Context, Ok, Result, bail, ensure};
+use byteorder::{BE, ByteOrder, LE, WriteBytesExt};
+use clap::ValueEnum;
+use image::{ImageBuffer, Rgba, RgbaImage};
+use serde::{Deserialize, Serialize};
+use totd_io::BinaryReader;
+use tracing::warn;
+
+/// Image with 16 bits per channel, as produced by `load_texture16_from_reader`.
+pub type Rgba16Image = ImageBuffer<Rgba<u16>, Vec<u16>>;
+
+/// Order in which pixels are stored in the raw data.
+#[derive(Clone, Copy, Default, PartialEq, Eq, ValueEnum, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum PixelOrder {
+    /// Pixels are stored row by row
+    #[default]
+    Row,
+    /// Pixels are stored column by column, i.e. the image is transposed
+    Column,
+}
+
+/// Order of the channels of RGBA4444 pixels, from the most to the least significant
+/// nibble of their little-endian 16 bit words.
+#[derive(Clone, Copy, Default, PartialEq, Eq, ValueEnum, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ChannelOrder {
+    /// The layout of the known banks, the first byte holding green and blue
+    /// and the second alpha and red
+    #[default]
+    Argb,
+    Rgba,
+    Bgra,
+    Abgr,
+}
+
+impl ChannelOrder {
+    /// Indices of the channels in RGBA order, from the most significant nibble down.
+    fn channels(self) -> [usize; 4] {
+        match self {
+            Self::Argb => [3, 0, 1, 2],
+            Self::Rgba => [0, 1, 2, 3],
+            Self::Bgra => [2, 1, 0, 3],
+            Self::Abgr => [3, 2, 1, 0],
+        }
+    }
+
+    /// Rearranges pixels decoded as `Argb` to this order.
+    fn reorder<T: Copy>(self, pixels: &mut [T]) {
+        if self == Self::Argb {
+            return;
+        }
+
+        let channels = self.channels();
+
+        for pixel in pixels.chunks_exact_mut(4) {
+            let nibbles = [pixel[3], pixel[0], pixel[1], pixel[2]];
+
+            for (&channel, nibble) in channels.iter().zip(nibbles) {
+                pixel[channel] = nibble;
+            }
+        }
+    }
+
+    /// Rearranges RGBA8888 pixels in this order back to `Argb`, the inverse of `reorder`.
+    pub fn restore(self, pixels: &mut [u8]) {
+        if self == Self::Argb {
+            return;
+        }
+
+        let channels = self.channels();
+
+        for pixel in pixels.chunks_exact_mut(4) {
+            let [a, r, g, b] = channels.map(|channel| pixel[channel]);
+
+            pixel.copy_from_slice(&[r, g, b, a]);
+        }
+    }
+}
+
+/// How the alpha channel of decoded textures is interpreted.
+#[derive(Clone, Copy, Default, PartialEq, Eq, ValueEnum, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum AlphaMode {
+    /// Keep the pixels as they are decoded
+    #[default]
+    Straight,
+    /// Multiply the color channels by alpha
+    Premultiplied,
+    /// Make every pixel fully opaque
+    Opaque,
+}
+
+/// Tiled layouts pixel data can be stored in.
+#[derive(Clone, Copy, PartialEq, Eq, ValueEnum, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum SwizzleMode {
+    /// Pixels within each tile are stored in Morton (Z-) order
+    Morton,
+    /// Pixels within each tile are stored row by row
+    Block,
+}
+
+/// Tiling of the pixel data. Tiles themselves are stored row by row.
+#[derive(Clone, Copy, Serialize, Deserialize)]
+pub struct Swizzle {
+    pub mode: SwizzleMode,
+    pub tile_width: u32,
+    pub tile_height: u32,
+}
+
+impl Swizzle {
+    /// Positions within a tile in the order their pixels are stored.
+    fn tile_order(&self) -> Vec<(usize, usize)> {
+        let mut order = Vec::with_capacity(self.tile_width as usize * self.tile_height as usize);
+
+        for y in 0..self.tile_height as usize {
+            for x in 0..self.tile_width as usize {
+                order.push((x, y));
+            }
+        }
+
+        if self.mode == SwizzleMode::Morton {
+            order.sort_by_key(|&(x, y)| morton_code(x, y));
+        }
+
+        order
+    }
+}
+
+pub use totd_io::Endian;
+
+/// Settings that control how pixel data is decoded.
+#[derive(Clone, Copy)]
+pub struct DecodeOptions {
+    pub pixel_order: PixelOrder,
+    pub swizzle: Option<Swizzle>,
+    pub packed: Option<PackedFormat>,
+    pub force_format: Option<PixelFormat>,
+    /// Absolute offset of the palette of indexed textures, if not directly after the pixel data.
+    pub palette_offset: Option<u64>,
+    pub palette_format: PixelFormat,
+    pub gamma: Option<f32>,
+    /// Converts the color channels from linear values to sRGB, after `gamma`.
+    pub srgb: bool,
+    /// Applies to textures and palettes in the RGBA4444 format.
+    pub channel_order: ChannelOrder,
+    pub alpha: AlphaMode,
+}
+
+/// Generic packed pixel format described by the bit width of each channel.
+///
+/// Pixels are little-endian words with the channels packed from the most
+/// significant bit down in R, G, B, A order. Channels with a width of zero
+/// are absent and decode as 0, or 255 for alpha.
+#[derive(Clone, Copy, Serialize, Deserialize)]
+pub struct PackedFormat {
+    widths: [u8; 4],
+}
+
+impl PackedFormat {
+    pub fn bits_per_pixel(&self) -> u32 {
+        self.widths.iter().map(|&width| width as u32).sum()
+    }
+
+    fn bytes_per_pixel(&self) -> usize {
+        self.bits_per_pixel() as usize / 8
+    }
+
+    pub fn read_pixel<R: Read>(&self, reader: &mut R) -> Result<[u8; 4]> {
+        Ok(self
+            .read_pixel_with_depth(reader, 8)?
+            .map(|channel| channel as u8))
+    }
+
+    pub fn read_pixel16<R: Read>(&self, reader: &mut R) -> Result<[u16; 4]> {
+        Ok(self
+            .read_pixel_with_depth(reader, 16)?
+            .map(|channel| channel as u16))
+    }
+
+    /// Encodes an RGBA8888 pixel by keeping the most significant bits of each channel.
+    pub fn write_pixel(&self, pixel: [u8; 4], out: &mut Vec<u8>) -> Result<()> {
+        let mut value = 0u32;
+
+        for (channel, &width) in pixel.iter().zip(&self.widths) {
+            value = value << width | (*channel as u32) >> (8 - width);
+        }
+
+        out.extend(&value.to_le_bytes()[..self.bytes_per_pixel()]);
+
+        Ok(())
+    }
+
+    /// Reads a pixel with each channel scaled to `depth` bits.
+    fn read_pixel_with_depth<R: Read>(&self, reader: &mut R, depth: u8) -> Result<[u32; 4]> {
+        let mut bytes = [0; 4];
+        reader.read_exact(&mut bytes[..self.bytes_per_pixel()])?;
+        let value = u32::from_le_bytes(bytes);
+
+        let mut shift = self.bits_per_pixel();
+        let mut pixel = [0, 0, 0, (1 << depth) - 1];
+
+        for (channel, &width) in pixel.iter_mut().zip(&self.widths) {
+            if width == 0 {
+                continue;
+            }
+
+            shift -= width as u32;
+            *channel = expand_bits((value >> shift) & ((1 << width) - 1), width, depth);
+        }
+
+        Ok(pixel)
+    }
+}
+
+impl FromStr for PackedFormat {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let widths = s
+            .split(',')
+            .map(|width| width.trim().parse::<u8>())
+            .collect::<Result<Vec<_>, _>>()?;
+        let widths: [u8; 4] = widths
+            .try_into()
+            .ok()
+            .context("expected four channel widths")?;
+
+        ensure!(
+            widths.iter().all(|&width| width <= 8),
+            "channel widths can't exceed 8 bits"
+        );
+
+        let format = Self { widths };
+        let bits = format.bits_per_pixel();
+
+        ensure!(
+            bits > 0 && bits.is_multiple_of(8),
+            "channel widths must add up to a whole number of bytes, got {bits} bits"
+        );
+
+        Ok(format)
+    }
+}
+
+/// Scales a `bits` wide value to `depth` bits by bit replication.
+fn expand_bits(value: u32, bits: u8, depth: u8) -> u32 {
+    let bits = bits as i32;
+    let mut expanded = 0;
+    let mut shift = depth as i32 - bits;
+
+    while shift > -bits {
+        expanded |= match shift {
+            0.. => value << shift,
+            _ => value >> -shift,
+        };
+        shift -= bits;
+    }
+
+    expanded
+}
+
+/// Parsed `.header` file listing the textures stored in the matching `.raw` file.
+pub struct BankHeader {
+    pub version: Option<u32>,
+    pub entries: Vec<TextureInfo>,
+}
+
+impl BankHeader {
+    /// Size of the version word some headers start with.
+    const VERSION_PREFIX_SIZE: u64 = 4;
+    /// Values accepted as a version word. The first word of an entry holds its width
+    /// and height, so it only falls in this range for entries without pixels.
+    const KNOWN_VERSIONS: RangeInclusive<u32> = 1..=0xffff;
+
+    pub fn from_path(path: impl AsRef<Path>, endian: Endian) -> Result<Self> {
+        Self::from_bytes(fs::read(path)?, endian)
+    }
+
+    pub fn from_bytes(header: Vec<u8>, endian: Endian) -> Result<Self> {
+        let mut header = Cursor::new(header);
+        let version = Self::read_version_prefix(&mut header, endian)?;
+        let entries = TextureInfo::all_from_reader(&mut header, endian)?;
+
+        Ok(Self { version, entries })
+    }
+
+    /// Parses the header section at the start of a file that also holds the pixel data.
+    ///
+    /// Without `header_size`, entries are read until the lowest texture offset seen so far,
+    /// i.e. the header is assumed to end where the first pixel data starts. A known version
+    /// word is taken to be a version prefix if the entries after it end at such an offset.
+    pub fn from_combined<R: Read + Seek>(
+        reader: &mut R,
+        header_size: Option<u64>,
+        endian: Endian,
+    ) -> Result<Self> {
+        let len = reader.seek(SeekFrom::End(0))?;
+        reader.seek(SeekFrom::Start(0))?;
+
+        if let Some(header_size) = header_size {
+            ensure!(
+                header_size <= len,
+                "header size {header_size:#x} is larger than the file of {len:#x} bytes"
+            );
+
+            let mut header = vec![0; header_size as usize];
+            reader.read_exact(&mut header)?;
+
+            return Self::from_bytes(header, endian);
+        }
+
+        let version = reader.read_u32(endian)?;
+
+        if Self::KNOWN_VERSIONS.contains(&version)
+            && let Result::Ok((entries, header_end)) = Self::read_combined_entries(reader, endian)
+            && header_end % 16 == Self::VERSION_PREFIX_SIZE
+        {
+            return Ok(Self {
+                version: Some(version),
+                entries,
+            });
+        }
+
+        reader.seek(SeekFrom::Start(0))?;
+
+        let (entries, _) = Self::read_combined_entries(reader, endian)?;
+
+        Ok(Self {
+            version: None,
+            entries,
+        })
+    }
+
+    /// Reads entries up to the lowest texture offset, which is returned along with them.
+    fn read_combined_entries<R: Read + Seek>(
+        reader: &mut R,
+        endian: Endian,
+    ) -> Result<(Vec<TextureInfo>, u64)> {
+        let mut entries = Vec::new();
+        let mut header_end = u64::MAX;
+
+        while reader.stream_position()? + 16 <= header_end {
+            let entry = TextureInfo::from_reader(reader, endian)?;
+
+            header_end = header_end.min(entry.offset as u64);
+            entries.push(entry);
+        }
+
+        Ok((entries, header_end))
+    }
+
+    /// Consumes the version word if the header has one.
+    ///
+    /// Entries are 16 bytes each, so a header whose size leaves a remainder
+    /// of exactly one version word is assumed to start with one, as long as
+    /// that word is a known version.
+    fn read_version_prefix<R>(reader: &mut R, endian: Endian) -> Result<Option<u32>>
+    where
+        R: Read + Seek,
+    {
+        let size = reader.seek(SeekFrom::End(0))?;
+        reader.seek(SeekFrom::Start(0))?;
+
+        if size % 16 != Self::VERSION_PREFIX_SIZE {
+            return Ok(None);
+        }
+
+        let version = reader.read_u32(endian)?;
+
+        if !Self::KNOWN_VERSIONS.contains(&version) {
+            warn!("header starts with unknown version {version:#x}, reading it without a version");
+            reader.seek(SeekFrom::Start(0))?;
+
+            return Ok(None);
+        }
+
+        Ok(Some(version))
+    }
+
+    /// Decodes each entry in the pixel format stored at its `texture_id` in `table`.
+    ///
+    /// The header's own `pixel_format` is kept, so it is written back unchanged by pack.
+    pub fn apply_format_table(&mut self, table: &[u8]) {
+        for entry in &mut self.entries {
+            match table.get(entry.texture_id as usize) {
+                Some(&pixel_format) => entry.table_pixel_format = Some(pixel_format),
+                None => warn!(
+                    "texture id {} is not in the format table, keeping pixel format {}",
+                    entry.texture_id, entry.pixel_format
+                ),
+            }
+        }
+    }
+
+    pub fn entries(&self) -> impl Iterator<Item = &TextureInfo> {
+        self.entries.iter()
+    }
+}
+
+/// A 16 byte texture entry of a bank header.
+#[derive(Serialize, Deserialize)]
+pub struct TextureInfo {
+    pub width: u16,
+    pub height: u16,
+    pub pixel_format: u8,
+    _unk0: u8,
+    _unk1: u16,
+    pub offset: u32,
+    pub texture_id: u16,
+    _unk4: u16,
+    /// Pixel format looked up with `apply_format_table`, which is decoded instead of
+    /// `pixel_format`. It isn't part of the header entry.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub table_pixel_format: Option<u8>,
+}
+
+impl TextureInfo {
+    pub fn all_from_reader<R>(reader: &mut R, endian: Endian) -> Result<Vec<TextureInfo>>
+    where
+        R: BufRead + Seek,
+    {
+        let start = reader.stream_position()?;
+        let size = reader.seek(SeekFrom::End(0))? - start;
+        reader.seek(SeekFrom::Start(start))?;
+
+        let num_headers = (size / 16) as usize;
+        let mut headers = Vec::with_capacity(num_headers);
+
+        for _ in 0..num_headers {
+            let header = Self::from_reader(reader, endian)?;
+
+            headers.push(header);
+        }
+
+        Ok(headers)
+    }
+
+    pub fn from_reader<R: Read>(r: &mut R, endian: Endian) -> Result<Self> {
+        Ok(Self {
+            width: r.read_u16(endian)?,
+            height: r.read_u16(endian)?,
+            pixel_format: r.read_u8()?,
+            _unk0: r.read_u8()?,
+            _unk1: r.read_u16(endian)?,
+            offset: r.read_u32(endian)?,
+            texture_id: r.read_u16(endian)?,
+            _unk4: r.read_u16(endian)?,
+            table_pixel_format: None,
+        })
+    }
+
+    pub fn write_to<W: Write>(&self, w: &mut W, endian: Endian) -> Result<()> {
+        match endian {
+            Endian::Little => self.write_fields::<LE, W>(w),
+            Endian::Big => self.write_fields::<BE, W>(w),
+        }
+    }
+
+    fn write_fields<B: ByteOrder, W: Write>(&self, w: &mut W) -> Result<()> {
+        w.write_u16::<B>(self.width)?;
+        w.write_u16::<B>(self.height)?;
+        w.write_u8(self.pixel_format)?;
+        w.write_u8(self._unk0)?;
+        w.write_u16::<B>(self._unk1)?;
+        w.write_u32::<B>(self.offset)?;
+        w.write_u16::<B>(self.texture_id)?;
+        w.write_u16::<B>(self._unk4)?;
+
+        Ok(())
+    }
+
+    /// The `pixel_format` byte the texture is decoded in, taking a format table into account.
+    pub fn effective_pixel_format(&self) -> u8 {
+        self.table_pixel_format.unwrap_or(self.pixel_format)
+    }
+
+    pub fn pixel_format(&self) -> Result<PixelFormat> {
+        PixelFormat::from_byte(self.effective_pixel_format())
+    }
+
+    /// Decoder used for this texture, taking overrides in `options` into account.
+    pub fn decoder(&self, options: &DecodeOptions) -> Result<Decoder> {
+        if let Some(packed) = options.packed {
+            return Ok(Decoder::Packed(packed));
+        }
+
+        if let Some(format) = options.force_format {
+            return Ok(Decoder::Format(format));
+        }
+
+        Ok(Decoder::Format(self.pixel_format()?))
+    }
+
+    /// Number of bytes the pixel data occupies in the raw file.
+    pub fn data_size(&self, options: &DecodeOptions) -> Result<u64> {
+        let num_pixels = self.width as u64 * self.height as u64;
+        let bits_per_pixel = self.decoder(options)?.bits_per_pixel() as u64;
+
+        Ok((num_pixels * bits_per_pixel).div_ceil(8))
+    }
+
+    pub fn byte_range(&self, options: &DecodeOptions) -> Result<Range<u64>> {
+        let start = self.offset as u64;
+
+        Ok(start..start + self.data_size(options)?)
+    }
+
+    /// Whether the texture has no pixels, as is the case for unused entries.
+    pub fn is_empty(&self) -> bool {
+        self.width == 0 || self.height == 0
+    }
+
+    /// Rejects entries that can't describe a texture stored in `reader`
+    /// before any pixel data is read.
+    fn validate<R: Seek>(&self, reader: &mut R, options: &DecodeOptions) -> Result<()> {
+        ensure!(
+            !self.is_empty(),
+            "texture has no pixels ({}x{})",
+            self.width,
+            self.height
+        );
+
+        let range = self.byte_range(options)?;
+        let len = reader.seek(SeekFrom::End(0))?;
+
+        ensure!(
+            range.end <= len,
+            "texture wants bytes {:#x}..{:#x} but the data is only {len:#x} bytes",
+            range.start,
+            range.end,
+        );
+
+        Ok(())
+    }
+
+    pub fn load_texture_from_reader<R>(
+        &self,
+        reader: &mut R,
+        options: &DecodeOptions,
+    ) -> Result<RgbaImage>
+    where
+        R: BufRead + Seek,
+    {
+        self.validate(reader, options)?;
+        reader.seek(SeekFrom::Start(self.offset as u64))?;
+
+        let mut pixels = self.read_pixel_data(reader, options)?;
+
+        if let Some(swizzle) = options.swizzle {
+            pixels = deswizzle_pixels(&pixels, self.width as usize, self.height as usize, swizzle);
+        }
+
+        if options.pixel_order == PixelOrder::Column {
+            pixels = transpose_pixels(&pixels, self.width as usize, self.height as usize);
+        }
+
+        if let Some(gamma) = options.gamma {
+            apply_curve(&mut pixels, |value| value.powf(1. / gamma));
+        }
+
+        if options.srgb {
+            apply_curve(&mut pixels, linear_to_srgb);
+        }
+
+        apply_alpha(&mut pixels, options.alpha);
+
+        let image = RgbaImage::from_vec(self.width as u32, self.height as u32, pixels)
+            .context("buffer too small")?;
+
+        Ok(image)
+    }
+
+    /// Like `load_texture_from_reader`, but keeps 16 bits of precision per channel.
+    pub fn load_texture16_from_reader<R>(
+        &self,
+        reader: &mut R,
+        options: &DecodeOptions,
+    ) -> Result<Rgba16Image>
+    where
+        R: BufRead + Seek,
+    {
+        self.validate(reader, options)?;
+        reader.seek(SeekFrom::Start(self.offset as u64))?;
+
+        let mut pixels = self.read_pixel_data16(reader, options)?;
+
+        if let Some(swizzle) = options.swizzle {
+            pixels = deswizzle_pixels(&pixels, self.width as usize, self.height as usize, swizzle);
+        }
+
+        if options.pixel_order == PixelOrder::Column {
+            pixels = transpose_pixels(&pixels, self.width as usize, self.height as usize);
+        }
+
+        if let Some(gamma) = options.gamma {
+            apply_curve16(&mut pixels, |value| value.powf(1. / gamma));
+        }
+
+        if options.srgb {
+            apply_curve16(&mut pixels, linear_to_srgb);
+        }
+
+        apply_alpha16(&mut pixels, options.alpha);
+
+        let image = Rgba16Image::from_vec(self.width as u32, self.height as u32, pixels)
+            .context("buffer too small")?;
+
+        Ok(image)
+    }
+
+    pub fn read_pixel_data16<R: Read + Seek>(
+        &self,
+        reader: &mut R,
+        options: &DecodeOptions,
+    ) -> Result<Vec<u16>> {
+        let decoder = self.decoder(options)?;
+
+        if let Decoder::Format(format) = decoder
+            && format.is_indexed()
+        {
+            let mut palette =
+                self.read_palette(reader, format, options, PixelFormat::read_pixel16)?;
+
+            if options.palette_format == PixelFormat::Rgba4444 {
+                options.channel_order.reorder(palette.as_flattened_mut());
+            }
+
+            return self.read_indexed_pixels(reader, format, &palette);
+        }
+
+        let num_pixels = self.width as usize * self.height as usize;
+        let data = self.read_data(reader, options)?;
+        let mut data = data.as_slice();
+        let mut pixels = Vec::with_capacity(num_pixels * 4); // RGBA16161616
+
+        for _ in 0..num_pixels {
+            pixels.extend(decoder.read_pixel16(&mut data)?);
+        }
+
+        if let Decoder::Format(PixelFormat::Rgba4444) = decoder {
+            options.channel_order.reorder(&mut pixels);
+        }
+
+        Ok(pixels)
+    }
+
+    pub fn read_pixel_data<R: Read + Seek>(
+        &self,
+        reader: &mut R,
+        options: &DecodeOptions,
+    ) -> Result<Vec<u8>> {
+        let decoder = self.decoder(options)?;
+
+        if let Decoder::Format(format) = decoder
+            && format.is_indexed()
+        {
+            let mut palette =
+                self.read_palette(reader, format, options, PixelFormat::read_pixel)?;
+
+            if options.palette_format == PixelFormat::Rgba4444 {
+                options.channel_order.reorder(palette.as_flattened_mut());
+            }
+
+            return self.read_indexed_pixels(reader, format, &palette);
+        }
+
+        let num_pixels = self.width as usize * self.height as usize;
+        let data = self.read_data(reader, options)?;
+        let mut data = data.as_slice();
+        let mut pixels = Vec::with_capacity(num_pixels * 4); // RGBA8888
+
+        for _ in 0..num_pixels {
+            pixels.extend(decoder.read_pixel(&mut data)?);
+        }
+
+        if let Decoder::Format(PixelFormat::Rgba4444) = decoder {
+            options.channel_order.reorder(&mut pixels);
+        }
+
+        Ok(pixels)
+    }
+
+    /// Reads all of the texture's pixel data with a single read, so the pixels
+    /// are decoded from memory instead of through many tiny reads of `reader`.
+    fn read_data<R: Read>(&self, reader: &mut R, options: &DecodeOptions) -> Result<Vec<u8>> {
+        let mut data = vec![0; self.data_size(options)? as usize];
+
+        reader.read_exact(&mut data)?;
+
+        Ok(data)
+    }
+
+    /// Reads the palette of an indexed texture, leaving `reader` at the position it started at.
+    ///
+    /// The palette is taken from `options.palette_offset` if given, and is otherwise
+    /// assumed to directly follow the pixel data.
+    fn read_palette<R, T>(
+        &self,
+        reader: &mut R,
+        format: PixelFormat,
+        options: &DecodeOptions,
+        read_color: fn(PixelFormat, &mut R) -> Result<[T; 4]>,
+    ) -> Result<Vec<[T; 4]>>
+    where
+        R: Read + Seek,
+    {
+        let palette_format = options.palette_format;
+
+        ensure!(
+            !palette_format.is_indexed(),
+            "palettes can't be stored as {palette_format:?}"
+        );
+
+        let palette_offset = match options.palette_offset {
+            Some(palette_offset) => palette_offset,
+            None => self.byte_range(options)?.end,
+        };
+        let num_colors = 1 << format.bits_per_pixel();
+        let data_start = reader.stream_position()?;
+
+        reader.seek(SeekFrom::Start(palette_offset))?;
+
+        let palette = (0..num_colors)
+            .map(|_| read_color(palette_format, reader))
+            .collect::<Result<Vec<_>>>()
+            .with_context(|| format!("failed to read the palette at {palette_offset:#x}"))?;
+
+        reader.seek(SeekFrom::Start(data_start))?;
+
+        Ok(palette)
+    }
+
+    fn read_indexed_pixels<R, T>(
+        &self,
+        reader: &mut R,
+        format: PixelFormat,
+        palette: &[[T; 4]],
+    ) -> Result<Vec<T>>
+    where
+        R: Read,
+        T: Copy,
+    {
+        let num_pixels = self.width as usize * self.height as usize;
+        let mut indices = vec![0; (num_pixels * format.bits_per_pixel() as usize).div_ceil(8)];
+
+        reader.read_exact(&mut indices)?;
+
+        if format == PixelFormat::Ci4 {
+            indices = indices
+                .iter()
+                .flat_map(|byte| [byte & 0xf, byte >> 4])
+                .take(num_pixels)
+                .collect();
+        }
+
+        Ok(indices
+            .iter()
+            .flat_map(|&index| palette[index as usize])
+            .collect())
+    }
+}
+
+/// Pixel formats identified by the `pixel_format` byte of a texture entry.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum PixelFormat {
+    /// Two bytes per pixel, the first holding green and blue, the second alpha and red,
+    /// each in the high and low nibble respectively. Read as little-endian 16 bit words,
+    /// this is ARGB from the most significant nibble down.
+    Rgba4444,
+    /// Little-endian 16 bit words with red in the most significant bits.
+    Rgb565,
+    /// Four bytes per pixel in R, G, B, A order.
+    Rgba8888,
+    /// One byte of intensity per pixel, fully opaque.
+    I8,
+    /// Four bit palette indices, two per byte with the first pixel in the low nibble.
+    Ci4,
+    /// One byte palette index per pixel.
+    Ci8,
+}
+
+impl PixelFormat {
+    pub fn from_byte(byte: u8) -> Result<Self> {
+        Ok(match byte {
+            0 => Self::Rgba4444,
+            1 => Self::Rgb565,
+            2 => Self::Rgba8888,
+            3 => Self::I8,
+            // Tentative, the values of the indexed formats haven't been confirmed yet
+            4 => Self::Ci4,
+            5 => Self::Ci8,
+            _ => bail!("unknown pixel format {byte:#04x}"),
+        })
+    }
+
+    pub fn bits_per_pixel(self) -> u32 {
+        match self {
+            Self::Rgba4444 => 16,
+            Self::Rgb565 => 16,
+            Self::Rgba8888 => 32,
+            Self::I8 => 8,
+            Self::Ci4 => 4,
+            Self::Ci8 => 8,
+        }
+    }
+
+    /// Whether pixels are indices into a palette rather than colors.
+    pub fn is_indexed(self) -> bool {
+        matches!(self, Self::Ci4 | Self::Ci8)
+    }
+
+    pub fn read_pixel<R: Read>(self, reader: &mut R) -> Result<[u8; 4]> {
+        Ok(match self {
+            Self::Rgba4444 => {
+                let (g, b) = read_44_pixel(reader)?;
+                let (a, r) = read_44_pixel(reader)?;
+
+                [r, g, b, a]
+            }
+            Self::Rgb565 => RGB565.read_pixel(reader)?,
+            Self::Rgba8888 => {
+                let mut pixel = [0; 4];
+                reader.read_exact(&mut pixel)?;
+
+                pixel
+            }
+            Self::I8 => {
+                let intensity = reader.read_u8()?;
+
+                [intensity, intensity, intensity, 255]
+            }
+            Self::Ci4 | Self::Ci8 => bail!("{self:?} pixels can only be decoded with a palette"),
+        })
+    }
+
+    /// Encodes an RGBA8888 pixel, the inverse of `read_pixel`.
+    pub fn write_pixel(self, pixel: [u8; 4], out: &mut Vec<u8>) -> Result<()> {
+        let [r, g, b, a] = pixel;
+
+        match self {
+            Self::Rgba4444 => out.extend([g & 0xf0 | b >> 4, a & 0xf0 | r >> 4]),
+            Self::Rgb565 => RGB565.write_pixel(pixel, out)?,
+            Self::Rgba8888 => out.extend(pixel),
+            Self::I8 => out.push(r),
+            Self::Ci4 | Self::Ci8 => bail!("encoding {self:?} textures is not supported"),
+        }
+
+        Ok(())
+    }
+
+    /// Like `read_pixel`, but scales every channel to 16 bits without losing precision.
+    pub fn read_pixel16<R: Read>(self, reader: &mut R) -> Result<[u16; 4]> {
+        Ok(match self {
+            Self::Rgba4444 => {
+                let (g, b) = read_44_pixel16(reader)?;
+                let (a, r) = read_44_pixel16(reader)?;
+
+                [r, g, b, a]
+            }
+            Self::Rgb565 => RGB565.read_pixel16(reader)?,
+            Self::Rgba8888 | Self::I8 | Self::Ci4 | Self::Ci8 => {
+                self.read_pixel(reader)?.map(scale_8bit_to_16bit)
+            }
+        })
+    }
+}
+
+const RGB565: PackedFormat = PackedFormat {
+    widths: [5, 6, 5, 0],
+};
+
+/// Decoder selected for a texture.
+#[derive(Clone, Copy)]
+pub enum Decoder {
+    Format(PixelFormat),
+    Packed(PackedFormat),
+}
+
+impl Decoder {
+    pub fn bits_per_pixel(&self) -> u32 {
+        match self {
+            Self::Format(format) => format.bits_per_pixel(),
+            Self::Packed(packed) => packed.bits_per_pixel(),
+        }
+    }
+
+    pub fn read_pixel<R: Read>(&self, reader: &mut R) -> Result<[u8; 4]> {
+        match self {
+            Self::Format(format) => format.read_pixel(reader),
+            Self::Packed(packed) => packed.read_pixel(reader),
+        }
+    }
+
+    pub fn read_pixel16<R: Read>(&self, reader: &mut R) -> Result<[u16; 4]> {
+        match self {
+            Self::Format(format) => format.read_pixel16(reader),
+            Self::Packed(packed) => packed.read_pixel16(reader),
+        }
+    }
+
+    pub fn write_pixel(&self, pixel: [u8; 4], out: &mut Vec<u8>) -> Result<()> {
+        match self {
+            Self::Format(format) => format.write_pixel(pixel, out),
+            Self::Packed(packed) => packed.write_pixel(pixel, out),
+        }
+    }
+}
+
+/// Reverses `DecodeOptions::gamma` and `DecodeOptions::srgb` on decoded RGBA8888 pixels.
+pub fn undo_color_correction(pixels: &mut [u8], gamma: Option<f32>, srgb: bool) {
+    if srgb {
+        apply_curve(pixels, srgb_to_linear);
+    }
+
+    if let Some(gamma) = gamma {
+        apply_curve(pixels, |value| value.powf(gamma));
+    }
+}
+
+/// Maps the color channels of RGBA8888 pixels through `curve`, leaving alpha untouched.
+///
+/// `curve` works on channel values scaled to `0.0..=1.0`.
+fn apply_curve(pixels: &mut [u8], curve: impl Fn(f32) -> f32) {
+    let mut lut = [0; 256];
+
+    for (value, mapped) in lut.iter_mut().enumerate() {
+        *mapped = (curve(value as f32 / 255.) * 255.).round() as u8;
+    }
+
+    for pixel in pixels.chunks_exact_mut(4) {
+        for channel in &mut pixel[..3] {
+            *channel = lut[*channel as usize];
+        }
+    }
+}
+
+/// Like `apply_curve`, but for 16 bit RGBA pixels.
+fn apply_curve16(pixels: &mut [u16], curve: impl Fn(f32) -> f32) {
+    for pixel in pixels.chunks_exact_mut(4) {
+        for channel in &mut pixel[..3] {
+            *channel = (curve(*channel as f32 / 65535.) * 65535.).round() as u16;
+        }
+    }
+}
+
+fn linear_to_srgb(value: f32) -> f32 {
+    match value <= 0.003_130_8 {
+        true => value * 12.92,
+        false => 1.055 * value.powf(1. / 2.4) - 0.055,
+    }
+}
+
+fn srgb_to_linear(value: f32) -> f32 {
+    match value <= 0.040_45 {
+        true => value / 12.92,
+        false => ((value + 0.055) / 1.055).powf(2.4),
+    }
+}
+
+/// Applies `mode` to RGBA8888 pixels.
+fn apply_alpha(pixels: &mut [u8], mode: AlphaMode) {
+    match mode {
+        AlphaMode::Straight => {}
+        AlphaMode::Premultiplied => {
+            for pixel in pixels.chunks_exact_mut(4) {
+                let alpha = pixel[3] as u32;
+
+                for channel in &mut pixel[..3] {
+                    *channel = ((*channel as u32 * alpha + 127) / 255) as u8;
+                }
+            }
+        }
+        AlphaMode::Opaque => {
+            for pixel in pixels.chunks_exact_mut(4) {
+                pixel[3] = u8::MAX;
+            }
+        }
+    }
+}
+
+/// Applies `mode` to 16 bit RGBA pixels.
+fn apply_alpha16(pixels: &mut [u16], mode: AlphaMode) {
+    match mode {
+        AlphaMode::Straight => {}
+        AlphaMode::Premultiplied => {
+            for pixel in pixels.chunks_exact_mut(4) {
+                let alpha = pixel[3] as u32;
+
+                for channel in &mut pixel[..3] {
+                    *channel = ((*channel as u32 * alpha + 32767) / 65535) as u16;
+                }
+            }
+        }
+        AlphaMode::Opaque => {
+            for pixel in pixels.chunks_exact_mut(4) {
+                pixel[3] = u16::MAX;
+            }
+        }
+    }
+}
+
+/// Reorders column-major RGBA pixels into row-major order.
+fn transpose_pixels<T: Copy + Default>(pixels: &[T], width: usize, height: usize) -> Vec<T> {
+    let mut transposed = vec![T::default(); pixels.len()];
+
+    for x in 0..width {
+        for y in 0..height {
+            let src = (x * height + y) * 4;
+            let dst = (y * width + x) * 4;
+
+            transposed[dst..dst + 4].copy_from_slice(&pixels[src..src + 4]);
+        }
+    }
+
+    transposed
+}
+
+/// Reorders RGBA pixels stored tile by tile into row-major order.
+///
+/// Tiles that extend past the edge of the texture are assumed to only store
+/// the pixels that lie inside of it.
+fn deswizzle_pixels<T: Copy + Default>(
+    pixels: &[T],
+    width: usize,
+    height: usize,
+    swizzle: Swizzle,
+) -> Vec<T> {
+    let tile_width = swizzle.tile_width as usize;
+    let tile_height = swizzle.tile_height as usize;
+    let tile_order = swizzle.tile_order();
+    let mut deswizzled = vec![T::default(); pixels.len()];
+    let mut src = 0;
+
+    for tile_y in (0..height).step_by(tile_height) {
+        for tile_x in (0..width).step_by(tile_width) {
+            for &(x, y) in &tile_order {
+                let (x, y) = (tile_x + x, tile_y + y);
+
+                if x >= width || y >= height {
+                    continue;
+                }
+
+                let dst = (y * width + x) * 4;
+
+                deswizzled[dst..dst + 4].copy_from_slice(&pixels[src..src + 4]);
+                src += 4;
+            }
+        }
+    }
+
+    deswizzled
+}
+
+/// Interleaves the bits of `x` and `y`, with `x` in the even bits.
+fn morton_code(x: usize, y: usize) -> u64 {
+    let mut code = 0;
+
+    for bit in 0..32 {
+        code |= ((x as u64 >> bit) & 1) << (2 * bit);
+        code |= ((y as u64 >> bit) & 1) << (2 * bit + 1);
+    }
+
+    code
+}
+
+fn read_44_pixel<R: Read>(r: &mut R) -> Result<(u8, u8)> {
+    let byte = r.read_u8()?;
+    let high = scale_4bit_to_8bit((byte >> 4) & 0b1111);
+    let low = scale_4bit_to_8bit(byte & 0b1111);
+
+    Ok((high, low))
+}
+
+fn scale_4bit_to_8bit(nibble: u8) -> u8 {
+    ((nibble as f32 / 15.) * 255.) as u8
+}
+
+fn read_44_pixel16<R: Read>(r: &mut R) -> Result<(u16, u16)> {
+    let byte = r.read_u8()?;
+    let high = scale_4bit_to_16bit((byte >> 4) & 0b1111);
+    let low = scale_4bit_to_16bit(byte & 0b1111);
+
+    Ok((high, low))
+}
+
+/// Maps each of the 16 possible values to an exact multiple of `0x1111`.
+fn scale_4bit_to_16bit(nibble: u8) -> u16 {
+    nibble as u16 * 0x1111
+}
+
+fn scale_8bit_to_16bit(value: u8) -> u16 {
+    value as u16 * 0x0101
+}
@@ -1,215 +1,1778 @@
+use std::borrow::Cow;
+use std::collections::{BTreeMap, HashMap};
+use std::fmt;
 use std::fs::{self, File};
-use std::io::{BufRead, BufReader, Cursor, Read, Seek, SeekFrom};
+use std::io::{self, BufRead, BufReader, BufWriter, Cursor, IsTerminal, Read, Seek, SeekFrom};
+use std::ops::RangeInclusive;
 use std::path::{Path, PathBuf};
+use std::str::FromStr;
 
-use anyhow::{Context, Ok, Result};
-use byteorder::{LE, ReadBytesExt};
-use clap::Parser;
-use image::{ImageFormat, RgbaImage, imageops};
-use serde::Serialize;
+use anyhow::{Context, Ok, Result, ensure};
+use base64::Engine;
+use base64::engine::general_purpose::STANDARD as BASE64;
+use clap::{ArgAction, Args, Parser, Subcommand, ValueEnum};
+use ddsfile::{D3DFormat, Dds, NewD3dParams};
+use image::codecs::gif::{GifEncoder, Repeat};
+use image::imageops::FilterType;
+use image::{Delay, Frame, ImageBuffer, ImageFormat, Pixel, RgbaImage, imageops};
+use memmap2::Mmap;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use tracing::{Level, debug, error, info, warn};
 use walkdir::{DirEntry, WalkDir};
 
+use pixelconv::{
+    AlphaMode, BankHeader, ChannelOrder, DecodeOptions, Endian, PackedFormat, PixelFormat,
+    PixelOrder, Swizzle, SwizzleMode, TextureInfo,
+};
+
+use crate::http::HttpReader;
+use crate::manifest::{BankManifest, ManifestTexture};
+use crate::montage::MontageTile;
+
+mod http;
+mod manifest;
+mod montage;
+mod pack;
+mod png_meta;
+
 #[derive(Parser)]
+#[command(args_conflicts_with_subcommands = true, subcommand_negates_reqs = true)]
 struct Cli {
+    #[command(subcommand)]
+    command: Option<Command>,
+    #[command(flatten)]
+    convert: Option<ConvertArgs>,
+    #[command(flatten)]
+    extract: ExtractArgs,
+    /// Print more details about what is being done, up to `-vvv`
+    #[arg(short, long, action = ArgAction::Count, global = true)]
+    verbose: u8,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// Check that the textures listed in the manifests of an output directory match the PNGs on disk
+    VerifyManifest { out_dir: PathBuf },
+    /// Rebuild header and raw files from the JSON and PNG files of an output directory
+    Pack {
+        out_dir: PathBuf,
+        header_dir: PathBuf,
+        raw_dir: PathBuf,
+    },
+    /// Convert a file holding a header immediately followed by the pixel data it describes
+    Combined {
+        /// Combined file, or an http(s) URL to fetch it from
+        file: PathBuf,
+        out_dir: PathBuf,
+        /// Size of the header section in bytes, by default where the first pixel data starts
+        #[arg(long, value_parser = parse_offset)]
+        header_size: Option<u64>,
+        #[command(flatten)]
+        extract: ExtractArgs,
+    },
+    /// Convert a single header and raw file pair into an output directory
+    Single {
+        /// Header file, or an http(s) URL to fetch it from
+        #[arg(long)]
+        header: PathBuf,
+        /// Raw file, or an http(s) URL to fetch it from
+        #[arg(long)]
+        raw: PathBuf,
+        #[arg(long)]
+        out: PathBuf,
+        #[command(flatten)]
+        extract: ExtractArgs,
+    },
+}
+
+/// Arguments of the default mode, converting a header and raw tree to PNGs.
+#[derive(Args)]
+struct ConvertArgs {
     header_dir: PathBuf,
+    /// Directory containing the raw files, or an http(s) URL to fetch them from
     raw_dir: PathBuf,
     out_dir: PathBuf,
+    /// Record completed banks in this checkpoint file and skip banks it already lists
+    #[arg(long, value_name = "CHECKPOINT")]
+    resume: Option<PathBuf>,
+    /// Only list where each texture id occurs, highlighting ids used more than once
+    #[arg(long)]
+    id_report: bool,
+}
+
+/// Options controlling how the textures of a bank are decoded and written.
+#[derive(Args)]
+struct ExtractArgs {
+    /// Byte order of the integers in the header files
+    #[arg(long, value_enum, default_value_t = Endian::Little)]
+    endian: Endian,
+    /// Only extract textures at these positions in their bank (e.g. 0-5,10)
+    #[arg(long, value_name = "INDICES")]
+    index: Option<RangeList<usize>>,
+    /// Only extract textures with these texture ids (e.g. 100-120,300)
+    #[arg(long, value_name = "IDS", aliases = ["texture-id", "texture-id-filter"])]
+    id: Option<RangeList<u16>>,
+    /// Print which byte ranges of each raw file are referenced by textures
+    #[arg(long)]
+    coverage_map: bool,
+    /// Order in which pixels are stored in the raw data
+    #[arg(long, value_enum, default_value_t = PixelOrder::Row)]
+    pixel_order: PixelOrder,
+    /// Undo a tiled layout of the pixel data
+    #[arg(long, value_enum)]
+    swizzle: Option<SwizzleMode>,
+    /// Size of the tiles used by --swizzle
+    #[arg(long, value_name = "WxH", default_value = "8x8", requires = "swizzle", value_parser = parse_tile_size)]
+    tile_size: Size,
+    /// Treat each texture as a grid of animation frames of this size (e.g. 16x16)
+    /// and additionally write it as an animation. A chain of frames is a grid of a
+    /// single row or column
+    #[arg(long, value_name = "WxH")]
+    animation_frame: Option<Size>,
+    /// File format of the animations written with --animation-frame
+    #[arg(long, value_enum, default_value_t = AnimationFormat::Gif)]
+    animation_format: AnimationFormat,
+    /// Frame rate of animations
+    #[arg(long, default_value_t = 10, value_parser = clap::value_parser!(u32).range(1..))]
+    fps: u32,
+    /// Compare decoded textures against PNGs in a directory laid out like `out_dir`
+    #[arg(long)]
+    reference_dir: Option<PathBuf>,
+    /// Maximum per-channel difference for a pixel to match its reference
+    #[arg(long, default_value_t = 0, requires = "reference_dir")]
+    tolerance: u8,
+    /// Maximum percentage of pixels that may exceed the tolerance for a texture to pass
+    #[arg(long, default_value_t = 0., requires = "reference_dir")]
+    max_differing_pixels: f64,
+    /// Take pixel formats from a table of one format byte per texture id
+    /// instead of the header entries
+    #[arg(long)]
+    format_table: Option<PathBuf>,
+    /// Also write a `montage.png` per bank showing every texture captioned with its metadata
+    #[arg(long)]
+    montage: bool,
+    /// Also write an `atlas.png` per bank with every texture laid out in a grid, in index order
+    #[arg(long)]
+    atlas: bool,
+    /// Transparent pixels between the cells of the atlas
+    #[arg(long, default_value_t = 2, requires = "atlas")]
+    atlas_padding: u32,
+    /// Embed each texture's written image base64-encoded in its JSON sidecar, as a PNG
+    /// if it is written in another format
+    #[arg(long)]
+    embed_png: bool,
+    /// Decode every texture as a packed format with these R,G,B,A channel widths in bits
+    /// (e.g. 5,6,5,0)
+    #[arg(long, value_name = "R,G,B,A")]
+    packed: Option<PackedFormat>,
+    /// Decode every texture in this format, ignoring the format in the header
+    #[arg(long, value_enum, conflicts_with = "packed")]
+    force_format: Option<PixelFormat>,
+    /// Read the palette of indexed textures from this offset in the raw file
+    /// instead of right after their pixel data
+    #[arg(long, value_name = "OFFSET", value_parser = parse_offset)]
+    palette_offset: Option<u64>,
+    /// Pixel format of the palette colors of indexed textures
+    #[arg(long, value_enum, default_value_t = PixelFormat::Rgba4444)]
+    palette_format: PixelFormat,
+    /// Order of the channels of RGBA4444 pixels, from the most significant nibble
+    /// of their little-endian 16 bit words down
+    #[arg(long, value_enum, default_value_t = ChannelOrder::Argb)]
+    channel_order: ChannelOrder,
+    /// How to interpret the alpha channel of decoded textures
+    #[arg(long, value_enum, default_value_t = AlphaMode::Straight)]
+    alpha: AlphaMode,
+    /// Only flip textures that are smaller than this size in both dimensions
+    #[arg(long, value_name = "WxH")]
+    flip_below: Option<Size>,
+    /// Write textures in the order they are stored instead of flipping them vertically
+    #[arg(long, conflicts_with = "flip_below")]
+    no_flip: bool,
+    /// Pad written textures with transparent pixels to the next power of two in both
+    /// dimensions. The padding goes to the right of and below the unflipped texture,
+    /// and the sidecar keeps the texture's own size
+    #[arg(long)]
+    pad_pot: bool,
+    /// Enlarge written textures by this integer factor, keeping pixels sharp, for
+    /// previewing small textures. Scaled textures can't be packed
+    #[arg(long, value_name = "N", conflicts_with = "pad_pot", value_parser = clap::value_parser!(u32).range(1..=64))]
+    scale: Option<u32>,
+    /// Apply gamma correction with this factor to the color channels of decoded textures
+    #[arg(long, value_parser = parse_gamma)]
+    gamma: Option<f32>,
+    /// Convert the color channels of decoded textures from linear values to sRGB
+    #[arg(long)]
+    srgb: bool,
+    /// Write a reference to an identical texture of the same bank instead of a duplicate PNG
+    #[arg(long)]
+    dedup: bool,
+    /// Stop at the first texture or header file that fails to convert
+    #[arg(long)]
+    strict: bool,
+    /// Only report which textures would be written, without writing anything
+    #[arg(long)]
+    dry_run: bool,
+    /// Memory-map raw files instead of reading them through a buffer, which is much
+    /// faster for large banks with many small textures. The files must not change
+    /// while they are being converted
+    #[arg(long)]
+    mmap: bool,
+    /// Replace the files of textures that already exist in the output directory.
+    /// Implied by `--resume`, which redoes interrupted banks
+    #[arg(long)]
+    force: bool,
+    /// Bits per channel of the written PNGs
+    #[arg(long, value_enum, default_value_t = BitDepth::Eight)]
+    bit_depth: BitDepth,
+    /// File format of the written textures. Pack reads all of them, but only PNGs
+    /// can be packed without their JSON sidecar
+    #[arg(long, value_enum, default_value_t = OutFormat::Png)]
+    out_format: OutFormat,
+    /// What the files of a texture are named after. Only index names can be packed again
+    #[arg(long, value_enum, default_value_t = NameBy::Index)]
+    name_by: NameBy,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, ValueEnum)]
+enum NameBy {
+    /// The position of the texture in its bank, e.g. `00.png`
+    Index,
+    /// The `texture_id` of the texture, e.g. `1234.png`. Textures sharing an id
+    /// get their index appended, e.g. `1234_05.png`
+    Id,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, ValueEnum)]
+enum OutFormat {
+    /// Carries the texture's header entry in a text chunk
+    Png,
+    Tga,
+    Bmp,
+    /// Uncompressed 32 bit RGBA
+    Dds,
+}
+
+impl OutFormat {
+    fn extension(self) -> &'static str {
+        match self {
+            Self::Png => "png",
+            Self::Tga => "tga",
+            Self::Bmp => "bmp",
+            Self::Dds => "dds",
+        }
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, ValueEnum)]
+enum AnimationFormat {
+    /// Only keeps fully transparent or opaque pixels
+    Gif,
+    /// Animated PNG, written as `NN.apng`
+    Apng,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, ValueEnum)]
+enum BitDepth {
+    #[value(name = "8")]
+    Eight,
+    /// Keeps the exact value of channels narrower than 8 bits
+    #[value(name = "16")]
+    Sixteen,
+}
+
+fn parse_gamma(s: &str) -> Result<f32> {
+    let gamma = s.parse::<f32>()?;
+
+    ensure!(
+        gamma.is_finite() && gamma > 0.,
+        "gamma must be a positive number"
+    );
+
+    Ok(gamma)
+}
+
+/// Parses a decimal or `0x` prefixed hexadecimal offset.
+fn parse_offset(s: &str) -> Result<u64> {
+    let offset = match s.strip_prefix("0x") {
+        Some(hex) => u64::from_str_radix(hex, 16)?,
+        None => s.parse()?,
+    };
+
+    Ok(offset)
+}
+
+fn parse_tile_size(s: &str) -> Result<Size> {
+    let size = s.parse::<Size>()?;
+
+    ensure!(
+        size.width > 0 && size.height > 0,
+        "tiles must be at least 1x1"
+    );
+
+    Ok(size)
+}
+
+impl ExtractArgs {
+    fn decode_options(&self) -> DecodeOptions {
+        DecodeOptions {
+            pixel_order: self.pixel_order,
+            swizzle: self.swizzle.map(|mode| Swizzle {
+                mode,
+                tile_width: self.tile_size.width,
+                tile_height: self.tile_size.height,
+            }),
+            packed: self.packed,
+            force_format: self.force_format,
+            palette_offset: self.palette_offset,
+            palette_format: self.palette_format,
+            gamma: self.gamma,
+            srgb: self.srgb,
+            channel_order: self.channel_order,
+            alpha: self.alpha,
+        }
+    }
+
+    /// Whether `entry` gets flipped vertically when it is written.
+    fn flips(&self, entry: &TextureInfo) -> bool {
+        if self.no_flip {
+            return false;
+        }
+
+        match self.flip_below {
+            Some(limit) => {
+                (entry.width as u32) < limit.width && (entry.height as u32) < limit.height
+            }
+            None => true,
+        }
+    }
+}
+
+/// State shared by all banks of a run.
+struct RunState {
+    format_table: Option<Vec<u8>>,
+    checkpoint: Option<Checkpoint>,
+    /// Textures not written because `--dedup` found an identical one in the same bank.
+    duplicates: usize,
+    /// Every `pixel_format` byte encountered, for the summary printed at the end.
+    pixel_formats: BTreeMap<u8, PixelFormatStats>,
+    /// Header files that were processed, whether they succeeded or not.
+    files: usize,
+    /// Failed header files and textures.
+    errors: usize,
+    /// Header files skipped because their raw file doesn't exist, reported together at the end.
+    missing_raws: Vec<PathBuf>,
+}
+
+struct PixelFormatStats {
+    count: usize,
+    /// Textures of this format that were decoded, or could be with `--dry-run`.
+    decoded: usize,
+    /// Textures of this format that failed to decode.
+    failed: usize,
+    /// Header and index of the first texture with this format.
+    example: (PathBuf, usize),
+}
+
+impl RunState {
+    fn new(args: &ExtractArgs, checkpoint: Option<Checkpoint>) -> Result<Self> {
+        ensure!(
+            args.bit_depth == BitDepth::Eight || args.out_format == OutFormat::Png,
+            "16 bit output is only supported for PNGs"
+        );
+
+        let format_table = args
+            .format_table
+            .as_ref()
+            .map(|path| fs::read(path).with_context(|| format!("{path:?}")))
+            .transpose()?;
+
+        Ok(Self {
+            format_table,
+            checkpoint,
+            duplicates: 0,
+            pixel_formats: BTreeMap::new(),
+            files: 0,
+            errors: 0,
+            missing_raws: Vec::new(),
+        })
+    }
+
+    fn record_pixel_format(&mut self, header_path: &Path, index: usize, pixel_format: u8) {
+        self.pixel_formats
+            .entry(pixel_format)
+            .or_insert_with(|| PixelFormatStats {
+                count: 0,
+                decoded: 0,
+                failed: 0,
+                example: (header_path.to_owned(), index),
+            })
+            .count += 1;
+    }
+
+    /// Records whether a texture recorded with `record_pixel_format` could be decoded.
+    fn record_decode(&mut self, pixel_format: u8, decoded: bool) {
+        if let Some(stats) = self.pixel_formats.get_mut(&pixel_format) {
+            match decoded {
+                true => stats.decoded += 1,
+                false => stats.failed += 1,
+            }
+        }
+    }
+
+    fn print_summary(&self, args: &ExtractArgs) {
+        if !self.pixel_formats.is_empty() {
+            println!("{:<6}  {:>5}  {:<7}  example", "format", "count", "decoded");
+        }
+
+        for (pixel_format, stats) in &self.pixel_formats {
+            let (path, index) = &stats.example;
+            let decoded = match (stats.decoded, stats.failed) {
+                (0, 0) => "-",
+                (_, 0) => "yes",
+                (0, _) => "no",
+                _ => "partly",
+            };
+
+            println!(
+                "{:<6}  {:>5}  {:<7}  {}#{index}",
+                format!("{pixel_format:#04x}"),
+                stats.count,
+                decoded,
+                path.display(),
+            );
+        }
+
+        if args.dedup {
+            info!(
+                "collapsed {} duplicate textures into references",
+                self.duplicates
+            );
+        }
+
+        if !self.missing_raws.is_empty() {
+            let mut headers = self
+                .missing_raws
+                .iter()
+                .map(|path| path.display().to_string())
+                .collect::<Vec<_>>();
+
+            headers.sort();
+
+            warn!(
+                "{} headers had no matching raw file: {}",
+                headers.len(),
+                headers.join(", ")
+            );
+        }
+
+        info!("processed {} files, {} errors", self.files, self.errors);
+    }
+
+    /// Fails the run if any header file or texture failed to convert.
+    fn finish(&self) -> Result<()> {
+        ensure!(self.errors == 0, "{} errors occurred", self.errors);
+
+        Ok(())
+    }
 }
 
 fn main() -> Result<()> {
     let cli = Cli::parse();
-    let header_dir = &cli.header_dir;
+
+    init_logging(cli.verbose);
+
+    match cli.command {
+        Some(Command::VerifyManifest { out_dir }) => manifest::verify_manifest(&out_dir),
+        Some(Command::Pack {
+            out_dir,
+            header_dir,
+            raw_dir,
+        }) => pack::pack(&out_dir, &header_dir, &raw_dir),
+        Some(Command::Combined {
+            file,
+            out_dir,
+            header_size,
+            extract,
+        }) => convert_combined(&extract, &file, &out_dir, header_size),
+        Some(Command::Single {
+            header,
+            raw,
+            out,
+            extract,
+        }) => convert_single(&extract, &header, &raw, &out),
+        None => {
+            let args = cli
+                .convert
+                .expect("conversion arguments are required without a subcommand");
+
+            convert(&args, &cli.extract)
+        }
+    }
+}
+
+/// Shows only warnings and errors by default, and more for every `-v`.
+fn init_logging(verbose: u8) {
+    let level = match verbose {
+        0 => Level::WARN,
+        1 => Level::INFO,
+        2 => Level::DEBUG,
+        _ => Level::TRACE,
+    };
+
+    tracing_subscriber::fmt()
+        .with_max_level(level)
+        .with_writer(io::stderr)
+        .with_ansi(io::stderr().is_terminal())
+        .with_target(false)
+        .without_time()
+        .init();
+}
+
+fn convert(args: &ConvertArgs, extract: &ExtractArgs) -> Result<()> {
+    let header_dir = &args.header_dir;
+
+    if args.id_report {
+        return print_id_report(header_dir, extract.endian);
+    }
+
+    let checkpoint = args.resume.as_deref().map(Checkpoint::load).transpose()?;
+    let mut state = RunState::new(extract, checkpoint)?;
 
     for entry in WalkDir::new(header_dir) {
-        if let Err(err) = handle_entry(&cli, entry) {
-            eprintln!("Error: {err:?}");
+        if let Err(err) = handle_entry(args, extract, &mut state, entry) {
+            error!("{err:?}");
+            state.errors += 1;
+        }
+
+        if extract.strict && state.errors > 0 {
+            warn!("stopping at the first error because of --strict");
+            break;
         }
     }
 
-    Ok(())
+    state.print_summary(extract);
+    state.finish()
+}
+
+/// Converts a file that holds the header section followed by the raw data.
+///
+/// Texture offsets are taken to be relative to the start of the file.
+fn convert_combined(
+    args: &ExtractArgs,
+    path: &Path,
+    out_dir: &Path,
+    header_size: Option<u64>,
+) -> Result<()> {
+    let mut state = RunState::new(args, None)?;
+    let mut data = open_raw(path, args.mmap).with_context(|| format!("{path:?}"))?;
+    let bank_header = BankHeader::from_combined(&mut data, header_size, args.endian)
+        .with_context(|| format!("{path:?}"))?;
+    let bank = Bank {
+        header_path: path,
+        header: bank_header,
+        raw_path: path,
+        raw_data: data,
+        out_dir: out_dir.to_owned(),
+        reference_dir: args.reference_dir.clone(),
+    };
+
+    state.files += 1;
+    convert_bank(args, &mut state, bank)?;
+    state.print_summary(args);
+    state.finish()
+}
+
+/// Converts one header and raw file into `out_dir`, without looking for other banks.
+fn convert_single(
+    args: &ExtractArgs,
+    header_path: &Path,
+    raw_path: &Path,
+    out_dir: &Path,
+) -> Result<()> {
+    let mut state = RunState::new(args, None)?;
+    let bank_header =
+        read_header(header_path, args.endian).with_context(|| format!("{header_path:?}"))?;
+    let raw_data = open_raw(raw_path, args.mmap).with_context(|| format!("{raw_path:?}"))?;
+    let bank = Bank {
+        header_path,
+        header: bank_header,
+        raw_path,
+        raw_data,
+        out_dir: out_dir.to_owned(),
+        reference_dir: args.reference_dir.clone(),
+    };
+
+    state.files += 1;
+    convert_bank(args, &mut state, bank)?;
+    state.print_summary(args);
+    state.finish()
 }
 
-fn handle_entry(cli: &Cli, entry: walkdir::Result<DirEntry>) -> Result<()> {
-    let header_dir = &cli.header_dir;
-    let raw_dir = &cli.raw_dir;
-    let out_dir = &cli.out_dir;
+fn handle_entry(
+    args: &ConvertArgs,
+    extract: &ExtractArgs,
+    state: &mut RunState,
+    entry: walkdir::Result<DirEntry>,
+) -> Result<()> {
+    let header_dir = &args.header_dir;
+    let raw_dir = &args.raw_dir;
+    let out_dir = &args.out_dir;
     let entry = entry?;
 
-    if entry.file_type().is_dir() {
+    if !is_header_file(&entry) {
         return Ok(());
     }
 
     let path = entry.path();
-    let extension = path.extension().and_then(|ext| ext.to_str());
+    let relative_path = path
+        .strip_prefix(header_dir)
+        .with_context(|| format!("{path:?}"))?;
 
-    if extension != Some("header") {
+    if let Some(checkpoint) = &state.checkpoint
+        && checkpoint.is_completed(relative_path)
+    {
         return Ok(());
     }
 
-    let relative_path = path
-        .strip_prefix(header_dir)
-        .with_context(|| format!("{path:?}"))?;
+    state.files += 1;
 
-    let mut raw_path = raw_dir.join(relative_path);
-    raw_path.set_extension("raw");
+    let raw_path = raw_path(raw_dir, relative_path)?;
 
     let mut out_dir = out_dir.join(relative_path);
     out_dir.set_extension("");
 
-    fs::create_dir_all(&out_dir).with_context(|| format!("{out_dir:?}"))?;
+    let reference_dir = extract.reference_dir.as_ref().map(|reference_dir| {
+        let mut reference_dir = reference_dir.join(relative_path);
+        reference_dir.set_extension("");
+        reference_dir
+    });
+
+    let bank_header = read_header(path, extract.endian).with_context(|| format!("{path:?}"))?;
+    let raw_data = match open_raw(&raw_path, extract.mmap) {
+        Result::Ok(raw_data) => raw_data,
+        Err(err) if is_not_found(&err) => {
+            state.missing_raws.push(path.to_owned());
+            return Ok(());
+        }
+        Err(err) => return Err(err).with_context(|| format!("{raw_path:?}")),
+    };
+    let bank = Bank {
+        header_path: path,
+        header: bank_header,
+        raw_path: &raw_path,
+        raw_data,
+        out_dir,
+        reference_dir,
+    };
+
+    let status = convert_bank(extract, state, bank)?;
+
+    if let Some(checkpoint) = &mut state.checkpoint
+        && !extract.dry_run
+    {
+        checkpoint.complete(relative_path, status)?;
+    }
 
-    let bank_header = BankHeader::from_path(path).with_context(|| format!("{path:?}"))?;
+    Ok(())
+}
+
+/// A bank to convert together with where its output goes.
+struct Bank<'a> {
+    header_path: &'a Path,
+    header: BankHeader,
+    raw_path: &'a Path,
+    raw_data: Box<dyn RawData>,
+    out_dir: PathBuf,
+    reference_dir: Option<PathBuf>,
+}
+
+fn convert_bank(args: &ExtractArgs, state: &mut RunState, bank: Bank) -> Result<BankStatus> {
+    let Bank {
+        header_path: path,
+        header: mut bank_header,
+        raw_path,
+        mut raw_data,
+        out_dir,
+        reference_dir,
+    } = bank;
+
+    if !args.dry_run {
+        fs::create_dir_all(&out_dir).with_context(|| format!("{out_dir:?}"))?;
+    }
+
+    if let Some(version) = bank_header.version {
+        info!("{path:?} has header version {version}");
+    }
+
+    if let Some(format_table) = &state.format_table {
+        bank_header.apply_format_table(format_table);
+    }
 
-    let raw_data = File::open(&raw_path).with_context(|| format!("{raw_path:?}"))?;
-    let mut raw_data = BufReader::new(raw_data);
+    info!(
+        "converting {} textures of {path:?}",
+        bank_header.entries.len()
+    );
+
+    let raw_len = raw_data.seek(SeekFrom::End(0))?;
+    let options = args.decode_options();
+
+    if args.coverage_map {
+        print_coverage_map(raw_path, raw_len, &bank_header, &options);
+    }
+
+    let mut decoded = Vec::new();
+    let mut texture_errors = 0;
+    let mut planned = 0;
+    let mut seen = HashMap::new();
+    // Files left in a bank that is resumed are from the interrupted run
+    let overwrite = args.force || state.checkpoint.is_some();
+    let mut manifest = BankManifest {
+        header: path.to_owned(),
+        raw: raw_path.to_owned(),
+        header_version: bank_header.version,
+        texture_count: bank_header.entries.len(),
+        textures: Vec::new(),
+    };
+
+    let names = texture_names(args.name_by, &bank_header, raw_path);
 
     for (index, entry) in bank_header.entries().enumerate() {
-        if let Err(err) = save_header(&out_dir, index, entry) {
-            eprintln!("failed to save header for texture {index} of {raw_path:?}: {err:?}");
+        if args.strict && texture_errors > 0 {
+            warn!("stopping at the first failing texture of {path:?} because of --strict");
+            break;
+        }
+
+        let name = names[index].as_str();
+
+        manifest.textures.push(ManifestTexture::new(index, entry));
+        state.record_pixel_format(path, index, entry.effective_pixel_format());
+
+        if args
+            .index
+            .as_ref()
+            .is_some_and(|filter| !filter.contains(index))
+            || args
+                .id
+                .as_ref()
+                .is_some_and(|filter| !filter.contains(entry.texture_id))
+        {
+            continue;
+        }
+
+        if entry.is_empty() {
+            warn!(
+                "skipping texture {index} of {raw_path:?}: it is {}x{}",
+                entry.width, entry.height
+            );
+
+            // Only the sidecar is written, pack needs it to rebuild the header
+            if !args.dry_run {
+                if let Some(path) = existing_output(args, &out_dir, name, overwrite) {
+                    error!("refusing to overwrite {path:?}, pass --force to replace it");
+                    texture_errors += 1;
+                } else if let Err(err) = save_header(args, &out_dir, name, entry, None, None) {
+                    error!("failed to save header for texture {index} of {raw_path:?}: {err:?}");
+                    texture_errors += 1;
+                }
+            }
+            continue;
+        }
+
+        // Entries whose format is unknown fail later with a more specific error
+        if let Result::Ok(range) = entry.byte_range(&options)
+            && range.end > raw_len
+        {
+            error!(
+                "texture {index} wants bytes {:#x}..{:#x} but raw file is only {raw_len:#x} bytes, skipping it ({raw_path:?})",
+                range.start, range.end,
+            );
+            texture_errors += 1;
+            continue;
+        }
+
+        if args.dry_run {
+            let decoder = entry.decoder(&options);
+
+            state.record_decode(entry.effective_pixel_format(), decoder.is_ok());
+
+            match decoder {
+                Result::Ok(_) => planned += 1,
+                Err(err) => {
+                    error!("texture {index} of {raw_path:?} can't be decoded: {err}");
+                    texture_errors += 1;
+                }
+            }
+            continue;
+        }
+
+        if let Some(path) = existing_output(args, &out_dir, name, overwrite) {
+            error!("refusing to overwrite {path:?}, pass --force to replace it");
+            texture_errors += 1;
             continue;
         }
 
-        if let Err(err) = save_texture(&out_dir, &mut raw_data, index, entry) {
-            eprintln!("failed to save texture {index} of {raw_path:?}: {err:?}");
+        debug!(
+            "decoding texture {index} of {raw_path:?}: {}x{}, pixel format {:#04x}",
+            entry.width,
+            entry.height,
+            entry.effective_pixel_format()
+        );
+
+        if let Err(err) = save_header(args, &out_dir, name, entry, None, None) {
+            error!("failed to save header for texture {index} of {raw_path:?}: {err:?}");
+            texture_errors += 1;
+            continue;
+        }
+
+        let reference_dir = reference_dir.as_deref();
+
+        let saved = save_texture(
+            args,
+            &out_dir,
+            reference_dir,
+            &mut raw_data,
+            &mut seen,
+            BankTexture {
+                index,
+                name,
+                info: entry,
+            },
+        );
+
+        state.record_decode(entry.effective_pixel_format(), saved.is_ok());
+
+        let SavedTexture {
+            image,
+            same_as,
+            differs_from_reference,
+            png,
+        } = match saved {
+            Result::Ok(saved) => saved,
+            Err(err) => {
+                error!("failed to save texture {index} of {raw_path:?}: {err:?}");
+                texture_errors += 1;
+                continue;
+            }
+        };
+
+        if differs_from_reference {
+            texture_errors += 1;
+        }
+
+        if let Some(texture) = manifest.textures.last_mut() {
+            texture.image = Some(format!(
+                "{}.{}",
+                names[same_as.unwrap_or(index)],
+                args.out_format.extension()
+            ));
+        }
+
+        if let Some(original) = same_as {
+            state.duplicates += 1;
+
+            if let Err(err) = save_header(args, &out_dir, name, entry, None, Some(original)) {
+                error!("failed to save header for texture {index} of {raw_path:?}: {err:?}");
+                texture_errors += 1;
+            }
+        } else if let Some(png) = png
+            && let Err(err) = save_header(args, &out_dir, name, entry, Some(&png), None)
+        {
+            error!("failed to embed texture {index} of {raw_path:?}: {err:?}");
+            texture_errors += 1;
+        }
+
+        if args.montage || args.atlas {
+            decoded.push((index, entry, image));
+        }
+    }
+
+    let status = BankStatus { texture_errors };
+
+    state.errors += texture_errors;
+
+    if args.dry_run {
+        println!(
+            "would write {planned} of {} textures to {out_dir:?}",
+            bank_header.entries.len()
+        );
+        return Ok(status);
+    }
+
+    if args.montage {
+        save_montage(&out_dir, &decoded)?;
+    }
+
+    if args.atlas {
+        save_atlas(&out_dir, &decoded, args.atlas_padding)?;
+    }
+
+    manifest.save(&out_dir)?;
+
+    Ok(status)
+}
+
+/// A file of the texture `name` that already exists and may not be overwritten.
+fn existing_output(
+    args: &ExtractArgs,
+    out_dir: &Path,
+    name: &str,
+    overwrite: bool,
+) -> Option<PathBuf> {
+    if overwrite {
+        return None;
+    }
+
+    [
+        format!("{name}.json"),
+        format!("{name}.{}", args.out_format.extension()),
+    ]
+    .into_iter()
+    .map(|file_name| out_dir.join(file_name))
+    .find(|path| path.exists())
+}
+
+/// File stems of the textures of a bank, see `--name-by`.
+fn texture_names(name_by: NameBy, bank_header: &BankHeader, raw_path: &Path) -> Vec<String> {
+    let mut indices_by_id = BTreeMap::<u16, Vec<usize>>::new();
+
+    for (index, entry) in bank_header.entries().enumerate() {
+        indices_by_id
+            .entry(entry.texture_id)
+            .or_default()
+            .push(index);
+    }
+
+    if name_by == NameBy::Id {
+        for (id, indices) in &indices_by_id {
+            if indices.len() > 1 {
+                warn!(
+                    "textures {indices:?} of {raw_path:?} share the id {id}, appending their index to their names"
+                );
+            }
+        }
+    }
+
+    bank_header
+        .entries()
+        .enumerate()
+        .map(|(index, entry)| {
+            let id = entry.texture_id;
+
+            match name_by {
+                NameBy::Index => format!("{index:02}"),
+                NameBy::Id if indices_by_id[&id].len() == 1 => format!("{id}"),
+                NameBy::Id => format!("{id}_{index:02}"),
+            }
+        })
+        .collect()
+}
+
+fn is_header_file(entry: &DirEntry) -> bool {
+    // Compared as an `OsStr`, so headers whose names aren't UTF-8 are picked up too
+    !entry.file_type().is_dir() && entry.path().extension().is_some_and(|ext| ext == "header")
+}
+
+fn print_id_report(header_dir: &Path, endian: Endian) -> Result<()> {
+    let mut locations = BTreeMap::<u16, Vec<(PathBuf, usize)>>::new();
+
+    for entry in WalkDir::new(header_dir) {
+        let entry = entry?;
+
+        if !is_header_file(&entry) {
             continue;
         }
+
+        let path = entry.path();
+        let relative_path = path.strip_prefix(header_dir)?;
+        let bank_header = match read_header(path, endian) {
+            Result::Ok(bank_header) => bank_header,
+            Err(err) => {
+                error!("{path:?}: {err:?}");
+                continue;
+            }
+        };
+
+        for (index, entry) in bank_header.entries().enumerate() {
+            locations
+                .entry(entry.texture_id)
+                .or_default()
+                .push((relative_path.to_owned(), index));
+        }
+    }
+
+    let mut duplicates = 0;
+
+    for (texture_id, locations) in &locations {
+        let marker = if locations.len() > 1 {
+            duplicates += 1;
+            " (duplicate)"
+        } else {
+            ""
+        };
+        let locations = locations
+            .iter()
+            .map(|(path, index)| format!("{}#{index}", path.display()))
+            .collect::<Vec<_>>()
+            .join(", ");
+
+        println!("{texture_id}{marker}: {locations}");
+    }
+
+    println!(
+        "{} texture ids, {duplicates} of them occur more than once",
+        locations.len()
+    );
+
+    Ok(())
+}
+
+/// Path or URL of the raw file belonging to the header at `relative_path`.
+fn raw_path(raw_dir: &Path, relative_path: &Path) -> Result<PathBuf> {
+    let relative_path = relative_path.with_extension("raw");
+
+    let Some(base_url) = raw_dir.to_str().filter(|raw_dir| http::is_url(raw_dir)) else {
+        return Ok(raw_dir.join(relative_path));
+    };
+
+    let mut url = base_url.trim_end_matches('/').to_owned();
+
+    for component in relative_path.components() {
+        let component = component.as_os_str().to_str();
+        let component = component.with_context(|| format!("{relative_path:?} is not UTF-8"))?;
+
+        url.push('/');
+        url.push_str(component);
+    }
+
+    Ok(PathBuf::from(url))
+}
+
+trait RawData: BufRead + Seek {}
+
+impl<R: BufRead + Seek> RawData for R {}
+
+/// Reads a header file through `open_raw`, so it can be fetched from a URL as well.
+fn read_header(header_path: &Path, endian: Endian) -> Result<BankHeader> {
+    let mut header = Vec::new();
+
+    open_raw(header_path, false)?.read_to_end(&mut header)?;
+
+    BankHeader::from_bytes(header, endian)
+}
+
+/// Opens a raw file, or fetches it on demand if it is a URL.
+///
+/// With `mmap`, local files are mapped into memory, so decoding reads straight
+/// from the mapped bytes instead of issuing a syscall whenever the buffer runs dry.
+fn open_raw(raw_path: &Path, mmap: bool) -> Result<Box<dyn RawData>> {
+    if let Some(url) = raw_path.to_str().filter(|raw_path| http::is_url(raw_path)) {
+        let reader = HttpReader::open(url)?;
+
+        return Ok(Box::new(BufReader::with_capacity(64 * 1024, reader)));
+    }
+
+    let file = File::open(raw_path)?;
+
+    if mmap {
+        // SAFETY: Raw files are only read, and `--mmap` documents that they must
+        // not be modified while mapped, which would be undefined behaviour.
+        let map = unsafe { Mmap::map(&file)? };
+
+        return Ok(Box::new(Cursor::new(map)));
+    }
+
+    Ok(Box::new(BufReader::new(file)))
+}
+
+/// Whether opening a file failed because it doesn't exist, locally or on the server.
+fn is_not_found(err: &anyhow::Error) -> bool {
+    let local = err
+        .downcast_ref::<io::Error>()
+        .is_some_and(|err| err.kind() == io::ErrorKind::NotFound);
+
+    local || http::is_not_found(err)
+}
+
+/// Banks that have been fully processed, persisted so an interrupted run can be resumed.
+#[derive(Default, Serialize, Deserialize)]
+struct Checkpoint {
+    #[serde(skip)]
+    path: PathBuf,
+    completed: BTreeMap<PathBuf, BankStatus>,
+}
+
+#[derive(Serialize, Deserialize)]
+struct BankStatus {
+    texture_errors: usize,
+}
+
+impl Checkpoint {
+    fn load(path: &Path) -> Result<Self> {
+        let mut checkpoint = match fs::read(path) {
+            Result::Ok(json) => {
+                serde_json::from_slice(&json).with_context(|| format!("{path:?}"))?
+            }
+            Err(err) if err.kind() == io::ErrorKind::NotFound => Self::default(),
+            Err(err) => return Err(err).with_context(|| format!("{path:?}")),
+        };
+
+        checkpoint.path = path.to_owned();
+
+        Ok(checkpoint)
+    }
+
+    fn is_completed(&self, relative_path: &Path) -> bool {
+        self.completed.contains_key(relative_path)
+    }
+
+    /// Records `relative_path` as completed and atomically rewrites the checkpoint file.
+    fn complete(&mut self, relative_path: &Path, status: BankStatus) -> Result<()> {
+        // JSON can only hold UTF-8 paths
+        if relative_path.to_str().is_none() {
+            warn!(
+                "{relative_path:?} is not UTF-8 and can't be recorded in the checkpoint, \
+                 it will be converted again when resuming"
+            );
+            return Ok(());
+        }
+
+        self.completed.insert(relative_path.to_owned(), status);
+
+        let json = serde_json::to_string_pretty(self)?;
+        let mut tmp_path = self.path.clone().into_os_string();
+        tmp_path.push(".tmp");
+
+        fs::write(&tmp_path, json).with_context(|| format!("{tmp_path:?}"))?;
+        fs::rename(&tmp_path, &self.path).with_context(|| format!("{:?}", self.path))?;
+
+        Ok(())
     }
+}
+
+fn save_montage(out_dir: &Path, decoded: &[(usize, &TextureInfo, RgbaImage)]) -> Result<()> {
+    let tiles = decoded
+        .iter()
+        .map(|(index, entry, image)| MontageTile {
+            image,
+            caption: vec![
+                format!("#{index} id {}", entry.texture_id),
+                format!(
+                    "{}x{} f {}",
+                    entry.width,
+                    entry.height,
+                    entry.effective_pixel_format()
+                ),
+            ],
+        })
+        .collect::<Vec<_>>();
+    let montage = montage::render_montage(&tiles);
+    let out_path = out_dir.join("montage.png");
+
+    montage
+        .save_with_format(&out_path, ImageFormat::Png)
+        .with_context(|| format!("{out_path:?}"))?;
 
     Ok(())
 }
 
-fn save_header(out_dir: &Path, index: usize, entry: &TextureInfo) -> Result<()> {
-    let out_path = out_dir.join(format!("{index:02}.json"));
+fn save_atlas(
+    out_dir: &Path,
+    decoded: &[(usize, &TextureInfo, RgbaImage)],
+    padding: u32,
+) -> Result<()> {
+    let images = decoded
+        .iter()
+        .map(|(_, _, image)| image)
+        .collect::<Vec<_>>();
+    let atlas = montage::render_atlas(&images, padding);
+    let out_path = out_dir.join("atlas.png");
+
+    atlas
+        .save_with_format(&out_path, ImageFormat::Png)
+        .with_context(|| format!("{out_path:?}"))?;
+
+    Ok(())
+}
+
+/// Contents of the JSON file written next to each texture.
+#[derive(Serialize)]
+struct Sidecar<'a> {
+    #[serde(flatten)]
+    info: &'a TextureInfo,
+    /// Whether the written image is flipped vertically compared to the raw data.
+    flipped: bool,
+    /// Byte order of the header the entry was read from, which pack writes it back in.
+    endian: Endian,
+    /// Layout options the pixel data was decoded with, which pack reverses or refuses.
+    #[serde(skip_serializing_if = "is_default")]
+    pixel_order: PixelOrder,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    swizzle: Option<Swizzle>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    packed: Option<PackedFormat>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    force_format: Option<PixelFormat>,
+    #[serde(skip_serializing_if = "is_default")]
+    channel_order: ChannelOrder,
+    /// The `--alpha` mode the texture was decoded with.
+    alpha: AlphaMode,
+    /// Color correction of `--gamma` and `--srgb`, which pack reverses.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    gamma: Option<f32>,
+    #[serde(skip_serializing_if = "std::ops::Not::not")]
+    srgb: bool,
+    /// Size of the written image if it was padded with `--pad-pot`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    padded_width: Option<u32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    padded_height: Option<u32>,
+    /// Factor the written image was enlarged by with `--scale`, `width` and
+    /// `height` remain those of the texture itself.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    scale: Option<u32>,
+    /// The written image, see `--embed-png`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    png_base64: Option<String>,
+    /// Index of an identical texture whose PNG was written instead of this one.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    same_as: Option<usize>,
+}
+
+fn is_default<T: Default + PartialEq>(value: &T) -> bool {
+    *value == T::default()
+}
+
+fn save_header(
+    args: &ExtractArgs,
+    out_dir: &Path,
+    name: &str,
+    entry: &TextureInfo,
+    png: Option<&[u8]>,
+    same_as: Option<usize>,
+) -> Result<()> {
+    let out_path = out_dir.join(format!("{name}.json"));
     let header_json_path = out_path.with_extension("json");
-    let header_json = serde_json::to_string_pretty(entry)?;
+    let padded_size = args.pad_pot.then(|| padded_size(entry));
+    let options = args.decode_options();
+    let sidecar = Sidecar {
+        info: entry,
+        flipped: args.flips(entry),
+        endian: args.endian,
+        pixel_order: options.pixel_order,
+        swizzle: options.swizzle,
+        packed: options.packed,
+        force_format: options.force_format,
+        channel_order: options.channel_order,
+        alpha: args.alpha,
+        gamma: args.gamma,
+        srgb: args.srgb,
+        padded_width: padded_size.map(|(width, _)| width),
+        padded_height: padded_size.map(|(_, height)| height),
+        scale: args.scale,
+        png_base64: png.map(|png| BASE64.encode(png)),
+        same_as,
+    };
+    let header_json = serde_json::to_string_pretty(&sidecar)?;
 
     fs::write(header_json_path, header_json)?;
 
     Ok(())
 }
 
-fn save_texture(
-    out_dir: &Path,
-    raw_data: &mut BufReader<File>,
+fn encode_png(image: &RgbaImage) -> Result<Vec<u8>> {
+    let mut png = Vec::new();
+
+    image.write_to(&mut Cursor::new(&mut png), ImageFormat::Png)?;
+
+    Ok(png)
+}
+
+/// A decoded texture and, with `--dedup`, the index of an identical texture
+/// written earlier in the same bank.
+struct SavedTexture {
+    image: RgbaImage,
+    same_as: Option<usize>,
+    /// Whether the texture failed the comparison against `--reference-dir`.
+    differs_from_reference: bool,
+    /// The written image as a PNG, with `--embed-png`.
+    png: Option<Vec<u8>>,
+}
+
+/// A texture of a bank and the stem of the files it is written to.
+struct BankTexture<'a> {
     index: usize,
+    name: &'a str,
+    info: &'a TextureInfo,
+}
+
+fn save_texture<R>(
+    args: &ExtractArgs,
+    out_dir: &Path,
+    reference_dir: Option<&Path>,
+    raw_data: &mut R,
+    seen: &mut HashMap<[u8; 32], usize>,
+    texture: BankTexture,
+) -> Result<SavedTexture>
+where
+    R: BufRead + Seek,
+{
+    let BankTexture {
+        index,
+        name,
+        info: entry,
+    } = texture;
+    let mut image = entry.load_texture_from_reader(raw_data, &args.decode_options())?;
+    let out_path = out_dir.join(format!("{name}.{}", args.out_format.extension()));
+
+    let flip = args.flips(entry);
+
+    // Flipped first, so duplicates hand back the image as it would have been written
+    if flip {
+        imageops::flip_vertical_in_place(&mut image);
+    }
+
+    let digest = args.dedup.then(|| pixel_digest(&image));
+
+    if let Some(&original) = digest.as_ref().and_then(|digest| seen.get(digest)) {
+        return Ok(SavedTexture {
+            image,
+            same_as: Some(original),
+            differs_from_reference: false,
+            png: None,
+        });
+    }
+
+    let written = output_image(args, &image, entry, flip);
+
+    match (args.out_format, args.bit_depth) {
+        (OutFormat::Png, BitDepth::Eight) => {
+            png_meta::save_png(&out_path, &written, entry, flip, args.endian)?
+        }
+        (OutFormat::Png, BitDepth::Sixteen) => {
+            let mut image = entry.load_texture16_from_reader(raw_data, &args.decode_options())?;
+
+            if flip {
+                imageops::flip_vertical_in_place(&mut image);
+            }
+
+            let image = output_image(args, &image, entry, flip);
+
+            png_meta::save_png16(&out_path, &image, entry, flip, args.endian)?;
+        }
+        (OutFormat::Tga, _) => written
+            .save_with_format(&out_path, ImageFormat::Tga)
+            .with_context(|| format!("{out_path:?}"))?,
+        (OutFormat::Bmp, _) => written
+            .save_with_format(&out_path, ImageFormat::Bmp)
+            .with_context(|| format!("{out_path:?}"))?,
+        (OutFormat::Dds, _) => {
+            save_dds(&out_path, &written).with_context(|| format!("{out_path:?}"))?
+        }
+    }
+
+    let png = match (args.embed_png, args.out_format) {
+        (false, _) => None,
+        // Read back so a 16 bit PNG is embedded as it was written
+        (true, OutFormat::Png) => {
+            Some(fs::read(&out_path).with_context(|| format!("{out_path:?}"))?)
+        }
+        (true, _) => Some(encode_png(&written)?),
+    };
+
+    if let Some(frame_size) = args.animation_frame {
+        save_animation(args, out_dir, name, &image, frame_size, flip)?;
+    }
+
+    let differs_from_reference = match reference_dir {
+        Some(reference_dir) => {
+            let reference_path = reference_dir.join(format!("{name}.png"));
+
+            !compare_with_reference(args, &image, &reference_path)?
+        }
+        None => false,
+    };
+
+    if let Some(digest) = digest {
+        seen.insert(digest, index);
+    }
+
+    Ok(SavedTexture {
+        image,
+        same_as: None,
+        differs_from_reference,
+        png,
+    })
+}
+
+/// The image that is written for a decoded texture, after applying `--pad-pot` or `--scale`.
+///
+/// Everything else, like the montage or reference comparisons, uses the decoded image.
+fn output_image<'a, P>(
+    args: &ExtractArgs,
+    image: &'a ImageBuffer<P, Vec<P::Subpixel>>,
     entry: &TextureInfo,
-) -> Result<(), anyhow::Error> {
-    let mut image = entry.load_texture_from_reader(raw_data)?;
-    let out_path = out_dir.join(format!("{index:02}.png"));
+    flipped: bool,
+) -> Cow<'a, ImageBuffer<P, Vec<P::Subpixel>>>
+where
+    P: Pixel + 'static,
+{
+    if args.pad_pot {
+        return Cow::Owned(pad_to_power_of_two(image, entry, flipped));
+    }
 
-    imageops::flip_vertical_in_place(&mut image);
+    if let Some(scale) = args.scale {
+        let (width, height) = image.dimensions();
 
-    image
-        .save_with_format(&out_path, ImageFormat::Png)
-        .with_context(|| format!("{out_path:?}"))?;
+        return Cow::Owned(imageops::resize(
+            image,
+            width * scale,
+            height * scale,
+            FilterType::Nearest,
+        ));
+    }
+
+    Cow::Borrowed(image)
+}
+
+/// Size a texture is padded to by `--pad-pot`.
+fn padded_size(entry: &TextureInfo) -> (u32, u32) {
+    (
+        (entry.width as u32).next_power_of_two(),
+        (entry.height as u32).next_power_of_two(),
+    )
+}
+
+/// Pads `image` to `padded_size` with transparent pixels.
+///
+/// The padding goes to the right of and below the texture as it is stored in the
+/// raw data, so it ends up above a `flipped` image.
+fn pad_to_power_of_two<P>(
+    image: &ImageBuffer<P, Vec<P::Subpixel>>,
+    entry: &TextureInfo,
+    flipped: bool,
+) -> ImageBuffer<P, Vec<P::Subpixel>>
+where
+    P: Pixel,
+{
+    let (width, height) = padded_size(entry);
+    let mut padded = ImageBuffer::new(width, height);
+    let y = match flipped {
+        true => height - image.height(),
+        false => 0,
+    };
+
+    imageops::replace(&mut padded, image, 0, y as i64);
+
+    padded
+}
+
+/// Writes `image` as an uncompressed 32 bit DDS texture.
+fn save_dds(path: &Path, image: &RgbaImage) -> Result<()> {
+    let mut dds = Dds::new_d3d(NewD3dParams {
+        height: image.height(),
+        width: image.width(),
+        depth: None,
+        format: D3DFormat::A8B8G8R8,
+        mipmap_levels: None,
+        caps2: None,
+    })?;
+
+    dds.data = image.as_raw().clone();
+    dds.write(&mut BufWriter::new(File::create(path)?))?;
 
     Ok(())
 }
 
-pub struct BankHeader {
-    entries: Vec<TextureInfo>,
+/// Hash identifying textures with the same dimensions and pixels.
+fn pixel_digest(image: &RgbaImage) -> [u8; 32] {
+    let mut hasher = Sha256::new();
+
+    hasher.update(image.width().to_le_bytes());
+    hasher.update(image.height().to_le_bytes());
+    hasher.update(image.as_raw());
+
+    hasher.finalize().into()
 }
 
-impl BankHeader {
-    fn from_path(path: impl AsRef<Path>) -> Result<Self> {
-        let header = fs::read(path)?;
-        let mut header = Cursor::new(header);
-        let entries = TextureInfo::all_from_reader(&mut header)?;
+/// Prints how much `image` differs from the PNG at `reference_path` and returns whether it passes.
+fn compare_with_reference(
+    args: &ExtractArgs,
+    image: &RgbaImage,
+    reference_path: &Path,
+) -> Result<bool> {
+    let reference = image::open(reference_path)
+        .with_context(|| format!("{reference_path:?}"))?
+        .into_rgba8();
 
-        Ok(Self { entries })
-    }
+    ensure!(
+        reference.dimensions() == image.dimensions(),
+        "{reference_path:?} is {}x{} but the decoded texture is {}x{}",
+        reference.width(),
+        reference.height(),
+        image.width(),
+        image.height(),
+    );
+
+    let mut max_difference = 0;
+    let mut differing_pixels = 0;
+
+    for (pixel, reference_pixel) in image.pixels().zip(reference.pixels()) {
+        let difference = pixel
+            .0
+            .iter()
+            .zip(reference_pixel.0)
+            .map(|(&channel, reference_channel)| channel.abs_diff(reference_channel))
+            .max()
+            .unwrap_or(0);
+
+        max_difference = max_difference.max(difference);
 
-    fn entries(&self) -> impl Iterator<Item = &TextureInfo> {
-        self.entries.iter()
+        if difference > args.tolerance {
+            differing_pixels += 1;
+        }
     }
+
+    let num_pixels = image.width() as u64 * image.height() as u64;
+    let differing_percentage = match num_pixels {
+        0 => 0.,
+        _ => differing_pixels as f64 / num_pixels as f64 * 100.,
+    };
+    let passed = differing_percentage <= args.max_differing_pixels;
+    let verdict = if passed { "pass" } else { "FAIL" };
+
+    println!(
+        "{verdict}: {reference_path:?} (max difference {max_difference}, {differing_pixels} of {num_pixels} pixels differ)"
+    );
+
+    Ok(passed)
 }
 
-#[derive(Serialize)]
-pub struct TextureInfo {
-    width: u16,
-    height: u16,
-    pixel_format: u8,
-    _unk0: u8,
-    _unk1: u16,
-    offset: u32,
-    texture_id: u16,
-    _unk4: u16,
-}
-
-impl TextureInfo {
-    fn all_from_reader<R>(reader: &mut R) -> Result<Vec<TextureInfo>>
-    where
-        R: BufRead + Seek,
-    {
-        let size = reader.seek(SeekFrom::End(0))?;
-        reader.seek(SeekFrom::Start(0))?;
+/// Splits `image` into row-major frames of `frame_size` and writes them as an animation.
+///
+/// The rows of frames of a `flipped` image are in reverse order, they are put back
+/// in order so that only each frame itself stays flipped.
+fn save_animation(
+    args: &ExtractArgs,
+    out_dir: &Path,
+    name: &str,
+    image: &RgbaImage,
+    frame_size: Size,
+    flipped: bool,
+) -> Result<()> {
+    let Size { width, height } = frame_size;
+
+    ensure!(
+        width > 0
+            && height > 0
+            && image.width().is_multiple_of(width)
+            && image.height().is_multiple_of(height),
+        "a {}x{} texture can't be split into {frame_size} frames",
+        image.width(),
+        image.height(),
+    );
+
+    let mut rows = (0..image.height())
+        .step_by(height as usize)
+        .collect::<Vec<_>>();
 
-        let num_headers = (size / 16) as usize;
-        let mut headers = Vec::with_capacity(num_headers);
+    if flipped {
+        rows.reverse();
+    }
+
+    let frames = rows.into_iter().flat_map(|y| {
+        (0..image.width())
+            .step_by(width as usize)
+            .map(move |x| imageops::crop_imm(image, x, y, width, height).to_image())
+    });
 
-        for _ in 0..num_headers {
-            let header = Self::from_reader(reader)?;
+    match args.animation_format {
+        AnimationFormat::Gif => save_gif(out_dir, name, frames, args.fps),
+        AnimationFormat::Apng => {
+            let num_frames = (image.width() / width) * (image.height() / height);
 
-            headers.push(header);
+            save_apng(out_dir, name, frames, frame_size, num_frames, args.fps)
         }
+    }
+}
+
+fn save_gif(
+    out_dir: &Path,
+    name: &str,
+    frames: impl Iterator<Item = RgbaImage>,
+    fps: u32,
+) -> Result<()> {
+    let out_path = out_dir.join(format!("{name}.gif"));
+    let out_file = File::create(&out_path).with_context(|| format!("{out_path:?}"))?;
+    let mut encoder = GifEncoder::new(BufWriter::new(out_file));
+    let delay = Delay::from_numer_denom_ms(1000, fps);
 
-        Ok(headers)
+    encoder.set_repeat(Repeat::Infinite)?;
+
+    for frame in frames {
+        encoder.encode_frame(Frame::from_parts(frame, 0, 0, delay))?;
     }
 
-    fn from_reader<R: Read>(r: &mut R) -> Result<Self> {
-        Ok(Self {
-            width: r.read_u16::<LE>()?,
-            height: r.read_u16::<LE>()?,
-            pixel_format: r.read_u8()?,
-            _unk0: r.read_u8()?,
-            _unk1: r.read_u16::<LE>()?,
-            offset: r.read_u32::<LE>()?,
-            texture_id: r.read_u16::<LE>()?,
-            _unk4: r.read_u16::<LE>()?,
-        })
+    Ok(())
+}
+
+fn save_apng(
+    out_dir: &Path,
+    name: &str,
+    frames: impl Iterator<Item = RgbaImage>,
+    frame_size: Size,
+    num_frames: u32,
+    fps: u32,
+) -> Result<()> {
+    let out_path = out_dir.join(format!("{name}.apng"));
+    let out_file = File::create(&out_path).with_context(|| format!("{out_path:?}"))?;
+    let mut encoder = png::Encoder::new(
+        BufWriter::new(out_file),
+        frame_size.width,
+        frame_size.height,
+    );
+    let fps = u16::try_from(fps).context("--fps is too high for APNG")?;
+
+    encoder.set_color(png::ColorType::Rgba);
+    encoder.set_depth(png::BitDepth::Eight);
+    // Zero plays loop forever
+    encoder.set_animated(num_frames, 0)?;
+    encoder.set_frame_delay(1, fps)?;
+
+    let mut writer = encoder.write_header()?;
+
+    for frame in frames {
+        writer.write_image_data(frame.as_raw())?;
     }
 
-    pub fn load_texture_from_reader<R>(&self, reader: &mut R) -> Result<RgbaImage>
-    where
-        R: BufRead + Seek,
-    {
-        reader.seek(SeekFrom::Start(self.offset as u64))?;
+    writer.finish()?;
+
+    Ok(())
+}
 
-        let pixels = self.read_pixel_data(reader)?;
-        let image = RgbaImage::from_vec(self.width as u32, self.height as u32, pixels)
-            .context("buffer too small")?;
+fn print_coverage_map(
+    raw_path: &Path,
+    raw_len: u64,
+    bank_header: &BankHeader,
+    options: &DecodeOptions,
+) {
+    let mut ranges = Vec::new();
 
-        Ok(image)
+    for (index, entry) in bank_header.entries().enumerate() {
+        match entry.byte_range(options) {
+            Result::Ok(range) => ranges.push(range),
+            Err(err) => warn!("texture {index} is left out of the coverage map: {err}"),
+        }
     }
 
-    fn read_pixel_data<R: Read>(&self, reader: &mut R) -> Result<Vec<u8>> {
-        let num_pixels = self.width as usize * self.height as usize;
-        let num_bytes = num_pixels * 4; // RGBA8888
-        let mut pixels = Vec::with_capacity(num_bytes);
+    ranges.sort_by_key(|range| range.start);
+
+    let mut covered = 0;
+    let mut gaps = Vec::new();
+    let mut pos = 0;
+
+    for range in ranges {
+        let start = range.start.min(raw_len);
+        let end = range.end.min(raw_len);
 
-        for _ in 0..num_pixels {
-            // TODO: respect pixel format
-            let (g, b) = read_44_pixel(reader)?;
-            let (a, r) = read_44_pixel(reader)?;
+        if start > pos {
+            gaps.push(pos..start);
+        }
 
-            pixels.push(r);
-            pixels.push(g);
-            pixels.push(b);
-            pixels.push(a);
+        if end > pos {
+            covered += end - start.max(pos);
+            pos = end;
         }
+    }
+
+    if pos < raw_len {
+        gaps.push(pos..raw_len);
+    }
+
+    let percentage = match raw_len {
+        0 => 100.,
+        _ => covered as f64 / raw_len as f64 * 100.,
+    };
+
+    println!("coverage of {raw_path:?}: {percentage:.2}% of {raw_len:#x} bytes");
+
+    for gap in gaps {
+        println!(
+            "  gap {:#x}..{:#x} ({} bytes)",
+            gap.start,
+            gap.end,
+            gap.end - gap.start
+        );
+    }
+}
+
+/// Image dimensions given on the command line as `WxH`.
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub struct Size {
+    width: u32,
+    height: u32,
+}
+
+impl FromStr for Size {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let (width, height) = s
+            .split_once('x')
+            .with_context(|| format!("expected WxH, got {s:?}"))?;
+
+        Ok(Self {
+            width: width.parse()?,
+            height: height.parse()?,
+        })
+    }
+}
 
-        Ok(pixels)
+impl fmt::Display for Size {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}x{}", self.width, self.height)
     }
 }
 
-fn read_44_pixel<R: Read>(r: &mut R) -> Result<(u8, u8)> {
-    let byte = r.read_u8()?;
-    let high = scale_4bit_to_8bit((byte >> 4) & 0b1111);
-    let low = scale_4bit_to_8bit((byte >> 0) & 0b1111);
+/// Comma separated list of numbers and inclusive ranges given on the command line,
+/// e.g. `0-5,10`.
+#[derive(Clone)]
+pub struct RangeList<T> {
+    ranges: Vec<RangeInclusive<T>>,
+}
 
-    Ok((high, low))
+impl<T: PartialOrd> RangeList<T> {
+    fn contains(&self, value: T) -> bool {
+        self.ranges.iter().any(|range| range.contains(&value))
+    }
 }
 
-fn scale_4bit_to_8bit(nibble: u8) -> u8 {
-    ((nibble as f32 / 15.) * 255.) as u8
+impl<T> FromStr for RangeList<T>
+where
+    T: FromStr + PartialOrd,
+    T::Err: std::error::Error + Send + Sync + 'static,
+{
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let mut ranges = Vec::new();
+
+        for item in s.split(',').map(str::trim) {
+            let (start, end) = item.split_once('-').unwrap_or((item, item));
+            let start = start
+                .trim()
+                .parse()
+                .with_context(|| format!("invalid range {item:?}"))?;
+            let end = end
+                .trim()
+                .parse()
+                .with_context(|| format!("invalid range {item:?}"))?;
+
+            ensure!(start <= end, "range {item:?} is empty");
+
+            ranges.push(start..=end);
+        }
+
+        Ok(Self { ranges })
+    }
 }
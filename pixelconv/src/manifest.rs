@@ -0,0 +1,192 @@
+use std::collections::{BTreeMap, BTreeSet};
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use anyhow::{Context, Result, ensure};
+use serde::{Deserialize, Serialize, Serializer};
+use tracing::warn;
+use walkdir::WalkDir;
+
+use pixelconv::TextureInfo;
+
+/// Contents of the `index.json` written into each bank's output directory.
+#[derive(Serialize, Deserialize)]
+pub struct BankManifest {
+    #[serde(serialize_with = "serialize_path_lossy")]
+    pub header: PathBuf,
+    #[serde(serialize_with = "serialize_path_lossy")]
+    pub raw: PathBuf,
+    pub header_version: Option<u32>,
+    pub texture_count: usize,
+    pub textures: Vec<ManifestTexture>,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct ManifestTexture {
+    pub index: usize,
+    pub texture_id: u16,
+    pub width: u16,
+    pub height: u16,
+    pub pixel_format: u8,
+    pub offset: u32,
+    /// File name of the written image, if the texture was extracted.
+    #[serde(alias = "png")]
+    pub image: Option<String>,
+}
+
+impl ManifestTexture {
+    pub fn new(index: usize, info: &TextureInfo) -> Self {
+        Self {
+            index,
+            texture_id: info.texture_id,
+            width: info.width,
+            height: info.height,
+            pixel_format: info.pixel_format,
+            offset: info.offset,
+            image: None,
+        }
+    }
+}
+
+impl BankManifest {
+    pub const FILE_NAME: &str = "index.json";
+
+    pub fn load(bank_dir: &Path) -> Result<Self> {
+        let path = bank_dir.join(Self::FILE_NAME);
+        let json = fs::read(&path).with_context(|| format!("{path:?}"))?;
+        let manifest = serde_json::from_slice(&json).with_context(|| format!("{path:?}"))?;
+
+        Ok(manifest)
+    }
+
+    pub fn save(&self, bank_dir: &Path) -> Result<()> {
+        let path = bank_dir.join(Self::FILE_NAME);
+        let json = serde_json::to_string_pretty(self)?;
+
+        fs::write(&path, json).with_context(|| format!("{path:?}"))?;
+
+        Ok(())
+    }
+}
+
+/// Writes paths that aren't UTF-8 with replacement characters instead of failing.
+///
+/// The paths in the manifest are only informational, so they don't need to round-trip.
+fn serialize_path_lossy<S: Serializer>(path: &Path, serializer: S) -> Result<S::Ok, S::Error> {
+    serializer.serialize_str(&path.to_string_lossy())
+}
+
+/// Texture related files found in a bank's output directory.
+#[derive(Default)]
+struct BankFiles {
+    has_manifest: bool,
+    sidecars: BTreeSet<String>,
+    images: BTreeSet<String>,
+}
+
+/// Cross-checks the manifest of every bank below `out_dir` against the files on disk.
+///
+/// Banks without an `index.json` are checked by pairing up the JSON sidecars and images.
+pub fn verify_manifest(out_dir: &Path) -> Result<()> {
+    let mut banks = BTreeMap::<PathBuf, BankFiles>::new();
+
+    for entry in WalkDir::new(out_dir) {
+        let entry = entry?;
+        let path = entry.path();
+
+        if entry.file_type().is_dir() {
+            continue;
+        }
+
+        let (Some(bank_dir), Some(file_name)) = (path.parent(), path.file_name()) else {
+            continue;
+        };
+        let Some(file_name) = file_name.to_str() else {
+            continue;
+        };
+
+        let bank = banks.entry(bank_dir.to_owned()).or_default();
+
+        if file_name == BankManifest::FILE_NAME {
+            bank.has_manifest = true;
+            continue;
+        }
+
+        let Some((stem, extension)) = file_name.rsplit_once('.') else {
+            continue;
+        };
+
+        // Textures are named after their index or id, see `--name-by`
+        if stem.is_empty() || !stem.bytes().all(|b| b.is_ascii_digit() || b == b'_') {
+            continue;
+        }
+
+        match extension {
+            "json" => bank.sidecars.insert(stem.to_owned()),
+            "png" | "tga" | "bmp" | "dds" => bank.images.insert(file_name.to_owned()),
+            _ => continue,
+        };
+    }
+
+    let mut checked = 0;
+    let mut discrepancies = Vec::new();
+
+    for (bank_path, files) in &banks {
+        let bank_dir = bank_path.display();
+
+        if files.has_manifest {
+            let manifest = BankManifest::load(bank_path)?;
+            let listed = manifest
+                .textures
+                .iter()
+                .filter_map(|texture| texture.image.clone())
+                .collect::<BTreeSet<_>>();
+
+            checked += manifest.textures.len();
+
+            for image in listed.difference(&files.images) {
+                discrepancies.push(format!("{bank_dir}/{image} is in the manifest but missing"));
+            }
+
+            for image in files.images.difference(&listed) {
+                discrepancies.push(format!("{bank_dir}/{image} is not in the manifest"));
+            }
+
+            continue;
+        }
+
+        for stem in &files.sidecars {
+            checked += 1;
+
+            if !files.images.iter().any(|image| image_stem(image) == stem) {
+                discrepancies.push(format!("{bank_dir}/{stem}.json has no matching image"));
+            }
+        }
+
+        for image in &files.images {
+            if !files.sidecars.contains(image_stem(image)) {
+                checked += 1;
+                discrepancies.push(format!("{bank_dir}/{image} is not listed by any JSON"));
+            }
+        }
+    }
+
+    for discrepancy in &discrepancies {
+        warn!("{discrepancy}");
+    }
+
+    println!(
+        "checked {checked} textures, found {} discrepancies",
+        discrepancies.len()
+    );
+
+    ensure!(discrepancies.is_empty(), "output directory is inconsistent");
+
+    Ok(())
+}
+
+fn image_stem(file_name: &str) -> &str {
+    file_name
+        .rsplit_once('.')
+        .map_or(file_name, |(stem, _)| stem)
+}
@@ -0,0 +1,116 @@
+use ab_glyph::{FontRef, PxScale};
+use image::{Rgba, RgbaImage, imageops};
+use imageproc::drawing;
+
+/// DejaVu Sans Mono, see `assets/DejaVuSansMono-LICENSE.txt`.
+const FONT: &[u8] = include_bytes!("../assets/DejaVuSansMono.ttf");
+const FONT_SIZE: f32 = 12.;
+const LINE_SPACING: u32 = 2;
+const PADDING: u32 = 4;
+const BACKGROUND: Rgba<u8> = Rgba([0x20, 0x20, 0x20, 0xff]);
+const CAPTION_BACKGROUND: Rgba<u8> = Rgba([0x00, 0x00, 0x00, 0xff]);
+const CAPTION_COLOR: Rgba<u8> = Rgba([0xff, 0xff, 0xff, 0xff]);
+
+/// A texture together with the caption lines shown beneath it.
+pub struct MontageTile<'a> {
+    pub image: &'a RgbaImage,
+    pub caption: Vec<String>,
+}
+
+/// Lays out `tiles` in a row-major grid, each cell sized to fit the largest texture
+/// and caption, with the caption rendered in a strip below the texture.
+pub fn render_montage(tiles: &[MontageTile]) -> RgbaImage {
+    let columns = (tiles.len() as f64).sqrt().ceil().max(1.) as u32;
+    let rows = (tiles.len() as u32).div_ceil(columns);
+
+    let caption_lines = tiles.iter().map(|tile| tile.caption.len()).max();
+    let caption_height = caption_lines.unwrap_or(0) as u32 * line_height() + PADDING;
+    let image_height = tiles.iter().map(|tile| tile.image.height()).max();
+    let image_height = image_height.unwrap_or(0);
+    let cell_width = tiles
+        .iter()
+        .map(|tile| {
+            let caption_width = tile.caption.iter().map(|line| text_width(line)).max();
+
+            tile.image.width().max(caption_width.unwrap_or(0))
+        })
+        .max()
+        .unwrap_or(0);
+    let cell_height = image_height + caption_height;
+
+    let mut montage = RgbaImage::from_pixel(
+        columns * (cell_width + PADDING) + PADDING,
+        rows * (cell_height + PADDING) + PADDING,
+        BACKGROUND,
+    );
+
+    for (i, tile) in tiles.iter().enumerate() {
+        let x = PADDING + (i as u32 % columns) * (cell_width + PADDING);
+        let y = PADDING + (i as u32 / columns) * (cell_height + PADDING);
+
+        imageops::overlay(&mut montage, tile.image, x as i64, y as i64);
+
+        let caption_y = y + image_height;
+
+        for cy in caption_y..caption_y + caption_height {
+            for cx in x..x + cell_width {
+                montage.put_pixel(cx, cy, CAPTION_BACKGROUND);
+            }
+        }
+
+        for (line_index, line) in tile.caption.iter().enumerate() {
+            let line_y = caption_y + PADDING / 2 + line_index as u32 * line_height();
+
+            draw_text(&mut montage, x, line_y, line);
+        }
+    }
+
+    montage
+}
+
+/// Lays out `images` in a row-major grid without captions, each cell sized to fit
+/// the largest image and separated by `padding` transparent pixels.
+pub fn render_atlas(images: &[&RgbaImage], padding: u32) -> RgbaImage {
+    let columns = (images.len() as f64).sqrt().ceil().max(1.) as u32;
+    let rows = (images.len() as u32).div_ceil(columns);
+    let cell_width = images.iter().map(|image| image.width()).max().unwrap_or(0);
+    let cell_height = images.iter().map(|image| image.height()).max().unwrap_or(0);
+
+    let mut atlas = RgbaImage::new(
+        (columns * (cell_width + padding)).saturating_sub(padding),
+        (rows * (cell_height + padding)).saturating_sub(padding),
+    );
+
+    for (i, image) in images.iter().enumerate() {
+        let x = (i as u32 % columns) * (cell_width + padding);
+        let y = (i as u32 / columns) * (cell_height + padding);
+
+        imageops::overlay(&mut atlas, *image, x as i64, y as i64);
+    }
+
+    atlas
+}
+
+fn font() -> FontRef<'static> {
+    FontRef::try_from_slice(FONT).expect("the bundled font is valid")
+}
+
+fn line_height() -> u32 {
+    FONT_SIZE.ceil() as u32 + LINE_SPACING
+}
+
+fn text_width(text: &str) -> u32 {
+    drawing::text_size(PxScale::from(FONT_SIZE), &font(), text).0
+}
+
+fn draw_text(image: &mut RgbaImage, x: u32, y: u32, text: &str) {
+    drawing::draw_text_mut(
+        image,
+        CAPTION_COLOR,
+        x as i32,
+        y as i32,
+        PxScale::from(FONT_SIZE),
+        &font(),
+        text,
+    );
+}
@@ -0,0 +1,372 @@
+use std::collections::BTreeMap;
+use std::fs::{self, File};
+use std::io::BufReader;
+use std::path::{Path, PathBuf};
+
+use anyhow::{Context, Result, bail, ensure};
+use byteorder::{BE, LE, WriteBytesExt};
+use clap::ValueEnum;
+use ddsfile::{D3DFormat, Dds};
+use image::{RgbaImage, imageops};
+use serde::Deserialize;
+use tracing::error;
+use walkdir::WalkDir;
+
+use pixelconv::{
+    AlphaMode, ChannelOrder, Decoder, Endian, PackedFormat, PixelFormat, PixelOrder, Swizzle,
+    TextureInfo,
+};
+
+use crate::manifest::BankManifest;
+use crate::png_meta::{self, EmbeddedInfo};
+
+/// Extensions of the images pack reads, in the order they are looked for.
+///
+/// These are all `--out-format`s, DDS files have to be uncompressed 32 bit RGBA as
+/// written by `convert`.
+const IMAGE_EXTENSIONS: [&str; 4] = ["png", "tga", "bmp", "dds"];
+
+/// Rebuilds `.header` and `.raw` files from the `NN.json` and `NN.png` files
+/// of every bank below `out_dir`. Textures can also be TGA, BMP or DDS files.
+///
+/// Textures whose JSON sidecar is missing are packed using the metadata embedded in their PNG.
+/// If a bank has an `index.json`, its header version is taken from it and the bank fails
+/// unless every texture it lists is present.
+pub fn pack(out_dir: &Path, header_dir: &Path, raw_dir: &Path) -> Result<()> {
+    let mut banks = BTreeMap::<PathBuf, BTreeMap<usize, PathBuf>>::new();
+
+    for entry in WalkDir::new(out_dir) {
+        let entry = entry?;
+        let path = entry.path();
+
+        // Banks whose textures are all missing are still checked against their manifest
+        if entry.file_name() == BankManifest::FILE_NAME {
+            let bank_dir = path.parent().context("manifest without a bank directory")?;
+
+            banks.entry(bank_dir.to_owned()).or_default();
+            continue;
+        }
+
+        if entry.file_type().is_dir()
+            || path.extension().is_none_or(|ext| {
+                ext != "json" && !IMAGE_EXTENSIONS.iter().any(|&image| ext == image)
+            })
+        {
+            continue;
+        }
+
+        let Some(index) = path
+            .file_stem()
+            .and_then(|stem| stem.to_str())
+            .and_then(|stem| stem.parse().ok())
+        else {
+            continue;
+        };
+
+        let bank_dir = path.parent().context("texture without a bank directory")?;
+
+        banks
+            .entry(bank_dir.to_owned())
+            .or_default()
+            .insert(index, path.with_extension(""));
+    }
+
+    let mut failed = 0;
+
+    for (bank_dir, textures) in &banks {
+        let relative_path = bank_dir.strip_prefix(out_dir)?;
+
+        if let Err(err) = pack_bank(bank_dir, textures, relative_path, header_dir, raw_dir) {
+            error!("failed to pack {bank_dir:?}: {err:?}");
+            failed += 1;
+        }
+    }
+
+    ensure!(
+        failed == 0,
+        "{failed} of {} banks failed to pack",
+        banks.len()
+    );
+
+    Ok(())
+}
+
+fn pack_bank(
+    bank_dir: &Path,
+    textures: &BTreeMap<usize, PathBuf>,
+    relative_path: &Path,
+    header_dir: &Path,
+    raw_dir: &Path,
+) -> Result<()> {
+    let manifest = match bank_dir.join(BankManifest::FILE_NAME).exists() {
+        true => Some(BankManifest::load(bank_dir)?),
+        false => None,
+    };
+
+    if let Some(manifest) = &manifest {
+        ensure!(
+            textures.len() == manifest.texture_count,
+            "found {} textures but {} lists {}",
+            textures.len(),
+            BankManifest::FILE_NAME,
+            manifest.texture_count,
+        );
+    }
+
+    let mut entries = Vec::new();
+    let mut raw = Vec::new();
+    let mut bank_endian = None;
+
+    for (expected_index, (&index, texture_path)) in textures.iter().enumerate() {
+        ensure!(
+            index == expected_index,
+            "texture {expected_index} is missing"
+        );
+
+        let sidecar_path = texture_path.with_extension("json");
+        let png_path = texture_path.with_extension("png");
+        let sidecar = match sidecar_path.exists() {
+            true => read_sidecar(&sidecar_path)?,
+            false => {
+                let embedded = png_meta::read_texture_info(&png_path)?.with_context(|| {
+                    format!("{png_path:?} has neither a JSON sidecar nor embedded metadata")
+                })?;
+
+                Sidecar::from_embedded(embedded)
+            }
+        };
+
+        if let Some(scale) = sidecar.scale {
+            bail!("{texture_path:?} was enlarged with --scale {scale} and can't be packed");
+        }
+
+        if sidecar.alpha != AlphaMode::Straight {
+            bail!(
+                "{texture_path:?} was written with --alpha {} and can't be packed",
+                sidecar.alpha.to_possible_value().unwrap().get_name()
+            );
+        }
+
+        if let Some(swizzle) = sidecar.swizzle {
+            bail!(
+                "{texture_path:?} was decoded with --swizzle {} and can't be packed",
+                swizzle.mode.to_possible_value().unwrap().get_name()
+            );
+        }
+
+        ensure!(
+            *bank_endian.get_or_insert(sidecar.endian) == sidecar.endian,
+            "{texture_path:?} was converted with a different --endian than the textures before it"
+        );
+
+        // Empty entries only have a sidecar, see `convert_bank`
+        if sidecar.info.is_empty() {
+            entries.push(sidecar.info);
+            continue;
+        }
+
+        let mut image_stem = texture_path.clone();
+
+        if let Some(original) = sidecar.same_as {
+            image_stem.set_file_name(format!("{original:02}"));
+        }
+
+        let info = &sidecar.info;
+        let padded = sidecar.padded_width.is_some() || sidecar.padded_height.is_some();
+        let (image_path, mut image) = load_image(&image_stem)?;
+
+        if sidecar.flipped {
+            imageops::flip_vertical_in_place(&mut image);
+        }
+
+        // Padding added by `--pad-pot` is to the right of and below the texture
+        if padded {
+            image =
+                imageops::crop_imm(&image, 0, 0, info.width as u32, info.height as u32).to_image();
+        }
+
+        pixelconv::undo_color_correction(&mut image, sidecar.gamma, sidecar.srgb);
+
+        let pixels = encode_texture(&sidecar, image).with_context(|| format!("{image_path:?}"))?;
+        let start = info.offset as usize;
+        let end = start + pixels.len();
+
+        if raw.len() < end {
+            raw.resize(end, 0);
+        }
+
+        raw[start..end].copy_from_slice(&pixels);
+        entries.push(sidecar.info);
+    }
+
+    let endian = bank_endian.unwrap_or_default();
+    let mut header = Vec::new();
+
+    if let Some(version) = manifest.and_then(|manifest| manifest.header_version) {
+        match endian {
+            Endian::Little => header.write_u32::<LE>(version)?,
+            Endian::Big => header.write_u32::<BE>(version)?,
+        }
+    }
+
+    for info in &entries {
+        info.write_to(&mut header, endian)?;
+    }
+
+    let header_path = header_dir.join(relative_path).with_extension("header");
+    let raw_path = raw_dir.join(relative_path).with_extension("raw");
+
+    for path in [&header_path, &raw_path] {
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent).with_context(|| format!("{parent:?}"))?;
+        }
+    }
+
+    fs::write(&header_path, header).with_context(|| format!("{header_path:?}"))?;
+    fs::write(&raw_path, raw).with_context(|| format!("{raw_path:?}"))?;
+
+    Ok(())
+}
+
+#[derive(Deserialize)]
+struct Sidecar {
+    #[serde(flatten)]
+    info: TextureInfo,
+    /// Index of the texture whose PNG holds this texture's pixels, see `--dedup`.
+    same_as: Option<usize>,
+    /// Whether the PNG was flipped vertically and has to be flipped back.
+    #[serde(default = "png_meta::default_flipped")]
+    flipped: bool,
+    /// Byte order the header is written in.
+    #[serde(default)]
+    endian: Endian,
+    /// How the pixel data was laid out, which is reproduced when encoding.
+    /// Swizzled textures can't be packed.
+    #[serde(default)]
+    pixel_order: PixelOrder,
+    swizzle: Option<Swizzle>,
+    packed: Option<PackedFormat>,
+    force_format: Option<PixelFormat>,
+    #[serde(default)]
+    channel_order: ChannelOrder,
+    /// Size of the PNG if it was padded by `--pad-pot` and has to be cropped.
+    padded_width: Option<u32>,
+    padded_height: Option<u32>,
+    /// Set for PNGs enlarged by `--scale`, which are only meant for previewing.
+    scale: Option<u32>,
+    /// Anything other than straight alpha can't be undone.
+    #[serde(default)]
+    alpha: AlphaMode,
+    /// Color correction applied when the PNG was written, which is reversed before packing.
+    gamma: Option<f32>,
+    #[serde(default)]
+    srgb: bool,
+}
+
+impl Sidecar {
+    /// Sidecar for a PNG written without one, which only carries the embedded metadata.
+    fn from_embedded(embedded: EmbeddedInfo) -> Self {
+        Self {
+            info: embedded.info,
+            same_as: None,
+            flipped: embedded.flipped,
+            endian: embedded.endian,
+            pixel_order: PixelOrder::Row,
+            swizzle: None,
+            packed: None,
+            force_format: None,
+            channel_order: ChannelOrder::Argb,
+            padded_width: None,
+            padded_height: None,
+            scale: None,
+            alpha: AlphaMode::Straight,
+            gamma: None,
+            srgb: false,
+        }
+    }
+}
+
+/// Loads the image of the texture whose files start with `stem`, in any of the `IMAGE_EXTENSIONS`.
+fn load_image(stem: &Path) -> Result<(PathBuf, RgbaImage)> {
+    let path = IMAGE_EXTENSIONS
+        .iter()
+        .map(|ext| stem.with_extension(ext))
+        .find(|path| path.exists())
+        .with_context(|| format!("{stem:?} has no image"))?;
+    let image = match path.extension().is_some_and(|ext| ext == "dds") {
+        true => load_dds(&path),
+        false => image::open(&path)
+            .map(|image| image.into_rgba8())
+            .map_err(Into::into),
+    }
+    .with_context(|| format!("{path:?}"))?;
+
+    Ok((path, image))
+}
+
+/// Loads an uncompressed 32 bit RGBA DDS file, which the `image` crate can't read.
+fn load_dds(path: &Path) -> Result<RgbaImage> {
+    let dds = Dds::read(BufReader::new(File::open(path)?))?;
+
+    ensure!(
+        dds.get_d3d_format() == Some(D3DFormat::A8B8G8R8),
+        "only uncompressed 32 bit RGBA DDS files can be packed"
+    );
+
+    let (width, height) = (dds.get_width(), dds.get_height());
+    let len = width as usize * height as usize * 4;
+
+    ensure!(dds.data.len() >= len, "DDS data is truncated");
+
+    RgbaImage::from_raw(width, height, dds.data[..len].to_vec()).context("invalid DDS size")
+}
+
+fn read_sidecar(path: &Path) -> Result<Sidecar> {
+    let info = fs::read(path).with_context(|| format!("{path:?}"))?;
+    let info = serde_json::from_slice(&info).with_context(|| format!("{path:?}"))?;
+
+    Ok(info)
+}
+
+/// Encodes `image` the way the texture described by `sidecar` was decoded.
+fn encode_texture(sidecar: &Sidecar, mut image: RgbaImage) -> Result<Vec<u8>> {
+    let info = &sidecar.info;
+
+    ensure!(
+        image.width() == info.width as u32 && image.height() == info.height as u32,
+        "image is {}x{} but the header says {}x{}",
+        image.width(),
+        image.height(),
+        info.width,
+        info.height,
+    );
+
+    let decoder = match (sidecar.packed, sidecar.force_format) {
+        (Some(packed), _) => Decoder::Packed(packed),
+        (None, Some(format)) => Decoder::Format(format),
+        (None, None) => Decoder::Format(info.pixel_format()?),
+    };
+
+    if let Decoder::Format(PixelFormat::Rgba4444) = decoder {
+        sidecar.channel_order.restore(&mut image);
+    }
+
+    let mut pixels = Vec::new();
+
+    match sidecar.pixel_order {
+        PixelOrder::Row => {
+            for pixel in image.pixels() {
+                decoder.write_pixel(pixel.0, &mut pixels)?;
+            }
+        }
+        PixelOrder::Column => {
+            for x in 0..image.width() {
+                for y in 0..image.height() {
+                    decoder.write_pixel(image.get_pixel(x, y).0, &mut pixels)?;
+                }
+            }
+        }
+    }
+
+    Ok(pixels)
+}
@@ -0,0 +1,136 @@
+use std::fs::File;
+use std::io::{BufReader, BufWriter};
+use std::path::Path;
+
+use anyhow::{Context, Result};
+use image::RgbaImage;
+use pixelconv::{Endian, Rgba16Image, TextureInfo};
+use png::{BitDepth, ColorType, Encoder};
+use serde::{Deserialize, Serialize};
+
+/// Keyword of the text chunk holding a texture's header entry as JSON.
+const TEXTURE_INFO_KEYWORD: &str = "pixelconv:texture-info";
+
+/// Metadata embedded by `save_png`.
+#[derive(Deserialize)]
+pub struct EmbeddedInfo {
+    #[serde(flatten)]
+    pub info: TextureInfo,
+    /// Whether the image was flipped vertically, see `--no-flip`.
+    #[serde(default = "default_flipped")]
+    pub flipped: bool,
+    /// Byte order of the header the entry was read from, see `--endian`.
+    #[serde(default)]
+    pub endian: Endian,
+}
+
+#[derive(Serialize)]
+struct EmbeddedInfoRef<'a> {
+    #[serde(flatten)]
+    info: &'a TextureInfo,
+    flipped: bool,
+    endian: Endian,
+}
+
+/// Textures written before `flipped` was recorded were always flipped.
+pub fn default_flipped() -> bool {
+    true
+}
+
+/// Writes `image` as a PNG carrying `info` in a text chunk.
+pub fn save_png(
+    path: &Path,
+    image: &RgbaImage,
+    info: &TextureInfo,
+    flipped: bool,
+    endian: Endian,
+) -> Result<()> {
+    write_png(
+        path,
+        image.width(),
+        image.height(),
+        BitDepth::Eight,
+        image,
+        EmbeddedInfoRef {
+            info,
+            flipped,
+            endian,
+        },
+    )
+    .with_context(|| format!("{path:?}"))
+}
+
+/// Like `save_png`, but writes 16 bits per channel.
+pub fn save_png16(
+    path: &Path,
+    image: &Rgba16Image,
+    info: &TextureInfo,
+    flipped: bool,
+    endian: Endian,
+) -> Result<()> {
+    let data = image
+        .iter()
+        .flat_map(|channel| channel.to_be_bytes())
+        .collect::<Vec<_>>();
+
+    write_png(
+        path,
+        image.width(),
+        image.height(),
+        BitDepth::Sixteen,
+        &data,
+        EmbeddedInfoRef {
+            info,
+            flipped,
+            endian,
+        },
+    )
+    .with_context(|| format!("{path:?}"))
+}
+
+fn write_png(
+    path: &Path,
+    width: u32,
+    height: u32,
+    bit_depth: BitDepth,
+    data: &[u8],
+    info: EmbeddedInfoRef,
+) -> Result<()> {
+    let file = BufWriter::new(File::create(path)?);
+    let mut encoder = Encoder::new(file, width, height);
+
+    encoder.set_color(ColorType::Rgba);
+    encoder.set_depth(bit_depth);
+    encoder.add_text_chunk(
+        TEXTURE_INFO_KEYWORD.to_owned(),
+        serde_json::to_string(&info)?,
+    )?;
+
+    let mut writer = encoder.write_header()?;
+
+    writer.write_image_data(data)?;
+    writer.finish()?;
+
+    Ok(())
+}
+
+/// Reads the header entry embedded by `save_png`, if the PNG has one.
+pub fn read_texture_info(path: &Path) -> Result<Option<EmbeddedInfo>> {
+    let file = BufReader::new(File::open(path).with_context(|| format!("{path:?}"))?);
+    let reader = png::Decoder::new(file)
+        .read_info()
+        .with_context(|| format!("{path:?}"))?;
+    let chunk = reader
+        .info()
+        .uncompressed_latin1_text
+        .iter()
+        .find(|chunk| chunk.keyword == TEXTURE_INFO_KEYWORD);
+
+    let Some(chunk) = chunk else {
+        return Ok(None);
+    };
+
+    let info = serde_json::from_str(&chunk.text).with_context(|| format!("{path:?}"))?;
+
+    Ok(Some(info))
+}
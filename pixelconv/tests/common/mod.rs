@@ -0,0 +1,38 @@
+// Every test binary only uses some of the helpers
+#![allow(dead_code)]
+
+use std::fs;
+use std::path::PathBuf;
+
+/// A 16 byte little-endian header entry.
+pub fn header_entry(width: u16, height: u16, pixel_format: u8, offset: u32, id: u16) -> Vec<u8> {
+    let mut entry = Vec::new();
+
+    entry.extend_from_slice(&width.to_le_bytes());
+    entry.extend_from_slice(&height.to_le_bytes());
+    entry.extend_from_slice(&[pixel_format, 0, 0, 0]);
+    entry.extend_from_slice(&offset.to_le_bytes());
+    entry.extend_from_slice(&id.to_le_bytes());
+    entry.extend_from_slice(&[0, 0]);
+
+    entry
+}
+
+/// Encodes an RGBA4444 pixel from its R, G, B and A nibbles.
+pub fn rgba4444([r, g, b, a]: [u8; 4]) -> [u8; 2] {
+    [g << 4 | b, a << 4 | r]
+}
+
+/// The RGBA8888 pixel an RGBA4444 pixel with these nibbles decodes to.
+pub fn expanded(nibbles: [u8; 4]) -> [u8; 4] {
+    nibbles.map(|nibble| nibble * 0x11)
+}
+
+pub fn temp_dir(name: &str) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("pixelconv_{name}_{}", std::process::id()));
+
+    let _ = fs::remove_dir_all(&dir);
+    fs::create_dir_all(&dir).unwrap();
+
+    dir
+}
@@ -0,0 +1,293 @@
+use std::fs;
+use std::io::{BufRead, BufReader, Write};
+use std::net::TcpListener;
+use std::path::Path;
+use std::process::Command;
+use std::thread;
+
+use base64::Engine;
+use base64::engine::general_purpose::STANDARD as BASE64;
+use common::{expanded, header_entry, rgba4444, temp_dir};
+use image::AnimationDecoder;
+use image::codecs::png::PngDecoder;
+
+mod common;
+
+const PIXELS: [[u8; 4]; 4] = [[1, 2, 3, 4], [5, 6, 7, 8], [9, 10, 11, 12], [13, 14, 15, 0]];
+
+/// Runs pixelconv with `args` on the bank `name` made of `entries`, returning whether it succeeded.
+fn convert(dir: &Path, name: &str, entries: &[Vec<u8>], args: &[&str]) -> bool {
+    let (header_dir, raw_dir) = (dir.join("headers"), dir.join("raws"));
+    let raw = PIXELS.into_iter().flat_map(rgba4444).collect::<Vec<_>>();
+
+    fs::create_dir_all(&header_dir).unwrap();
+    fs::create_dir_all(&raw_dir).unwrap();
+    fs::write(header_dir.join(format!("{name}.header")), entries.concat()).unwrap();
+    fs::write(raw_dir.join(format!("{name}.raw")), raw).unwrap();
+
+    Command::new(env!("CARGO_BIN_EXE_pixelconv"))
+        .args([&header_dir, &raw_dir, &dir.join("out")])
+        .args(args)
+        .output()
+        .unwrap()
+        .status
+        .success()
+}
+
+#[test]
+fn textures_are_written_flipped_vertically() {
+    let dir = temp_dir("flip");
+
+    assert!(convert(&dir, "bank", &[header_entry(2, 2, 0, 0, 100)], &[]));
+
+    let image = image::open(dir.join("out/bank/00.png"))
+        .unwrap()
+        .into_rgba8();
+    let [top_left, top_right, bottom_left, bottom_right] = PIXELS.map(expanded);
+
+    assert_eq!(
+        image.into_raw(),
+        [bottom_left, bottom_right, top_left, top_right].concat()
+    );
+
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn empty_entries_are_skipped() {
+    let dir = temp_dir("empty");
+
+    assert!(convert(
+        &dir,
+        "bank",
+        &[header_entry(0, 0, 0, 0, 100), header_entry(1, 1, 0, 0, 101)],
+        &[]
+    ));
+    assert!(!dir.join("out/bank/00.png").exists());
+    assert!(dir.join("out/bank/00.json").exists());
+    assert!(dir.join("out/bank/01.png").exists());
+
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn entries_reaching_past_the_raw_data_fail_the_run() {
+    let dir = temp_dir("offset");
+
+    assert!(!convert(
+        &dir,
+        "bank",
+        &[header_entry(2, 2, 0, 4, 100), header_entry(1, 1, 0, 0, 101)],
+        &[]
+    ));
+    assert!(!dir.join("out/bank/00.png").exists());
+    // The other textures of the bank are still written
+    assert!(dir.join("out/bank/01.png").exists());
+
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn strict_stops_at_the_first_failing_texture() {
+    let dir = temp_dir("strict");
+
+    assert!(!convert(
+        &dir,
+        "bank",
+        &[header_entry(2, 2, 9, 0, 100), header_entry(2, 2, 0, 0, 101)],
+        &["--strict"]
+    ));
+    assert!(!dir.join("out/bank/01.png").exists());
+
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn textures_differing_from_their_reference_fail_the_run() {
+    let dir = temp_dir("reference");
+    let reference_dir = dir.join("reference");
+    let reference_arg = format!("--reference-dir={}", reference_dir.display());
+
+    fs::create_dir_all(reference_dir.join("bank")).unwrap();
+    image::RgbaImage::new(2, 2)
+        .save(reference_dir.join("bank/00.png"))
+        .unwrap();
+
+    assert!(!convert(
+        &dir,
+        "bank",
+        &[header_entry(2, 2, 0, 0, 100)],
+        &[&reference_arg]
+    ));
+
+    // A reference equal to the decoded texture passes
+    fs::copy(
+        dir.join("out/bank/00.png"),
+        reference_dir.join("bank/00.png"),
+    )
+    .unwrap();
+
+    assert!(convert(
+        &dir,
+        "bank",
+        &[header_entry(2, 2, 0, 0, 100)],
+        &[&reference_arg, "--force"]
+    ));
+
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn raws_missing_on_the_server_are_reported_as_missing() {
+    let dir = temp_dir("http_missing");
+    let header_dir = dir.join("headers");
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let raw_url = format!("http://{}/raws", listener.local_addr().unwrap());
+
+    // Answers every request with 404 Not Found
+    thread::spawn(move || {
+        for stream in listener.incoming() {
+            let mut stream = stream.unwrap();
+            let mut reader = BufReader::new(&stream);
+            let mut line = String::new();
+
+            while reader.read_line(&mut line).unwrap() > 2 {
+                line.clear();
+            }
+
+            let response =
+                "HTTP/1.1 404 Not Found\r\ncontent-length: 0\r\nconnection: close\r\n\r\n";
+
+            stream.write_all(response.as_bytes()).unwrap();
+        }
+    });
+
+    fs::create_dir_all(&header_dir).unwrap();
+    fs::write(
+        header_dir.join("bank.header"),
+        header_entry(2, 2, 0, 0, 100),
+    )
+    .unwrap();
+
+    let output = Command::new(env!("CARGO_BIN_EXE_pixelconv"))
+        .args([
+            header_dir.as_os_str(),
+            raw_url.as_ref(),
+            dir.join("out").as_os_str(),
+        ])
+        .output()
+        .unwrap();
+    let stderr = String::from_utf8_lossy(&output.stderr);
+
+    assert!(output.status.success(), "{stderr}");
+    assert!(
+        stderr.contains("1 headers had no matching raw file"),
+        "{stderr}"
+    );
+
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn duplicates_are_flipped_like_their_original() {
+    let dir = temp_dir("dedup");
+
+    assert!(convert(
+        &dir,
+        "bank",
+        &[header_entry(2, 2, 0, 0, 100), header_entry(2, 2, 0, 0, 101)],
+        &["--dedup", "--atlas"]
+    ));
+
+    let atlas = image::open(dir.join("out/bank/atlas.png"))
+        .unwrap()
+        .into_rgba8();
+    let original = image::imageops::crop_imm(&atlas, 0, 0, 2, 2).to_image();
+    let duplicate = image::imageops::crop_imm(&atlas, 4, 0, 2, 2).to_image();
+
+    assert!(!dir.join("out/bank/01.png").exists());
+    assert_eq!(duplicate, original);
+
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn embedded_pngs_match_the_written_file() {
+    let dir = temp_dir("embed");
+
+    assert!(convert(
+        &dir,
+        "bank",
+        &[header_entry(2, 2, 0, 0, 100)],
+        &["--embed-png", "--scale=2"]
+    ));
+
+    let sidecar = fs::read(dir.join("out/bank/00.json")).unwrap();
+    let sidecar = serde_json::from_slice::<serde_json::Value>(&sidecar).unwrap();
+    let embedded = BASE64
+        .decode(sidecar["png_base64"].as_str().unwrap())
+        .unwrap();
+
+    assert_eq!(embedded, fs::read(dir.join("out/bank/00.png")).unwrap());
+
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn animation_frames_are_in_order_after_flipping() {
+    let dir = temp_dir("animation");
+
+    assert!(convert(
+        &dir,
+        "bank",
+        &[header_entry(2, 2, 0, 0, 100)],
+        &["--animation-frame=2x1", "--animation-format=apng"]
+    ));
+
+    let file = BufReader::new(fs::File::open(dir.join("out/bank/00.apng")).unwrap());
+    let frames = PngDecoder::new(file)
+        .unwrap()
+        .apng()
+        .unwrap()
+        .into_frames()
+        .map(|frame| frame.unwrap().into_buffer().into_raw())
+        .collect::<Vec<_>>();
+    let [top_left, top_right, bottom_left, bottom_right] = PIXELS.map(expanded);
+
+    assert_eq!(
+        frames,
+        [
+            [top_left, top_right].concat(),
+            [bottom_left, bottom_right].concat()
+        ]
+    );
+
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn the_format_summary_reports_whether_textures_decoded() {
+    let dir = temp_dir("summary");
+    let (header_dir, raw_dir) = (dir.join("headers"), dir.join("raws"));
+    let header = [header_entry(2, 2, 0, 0, 100), header_entry(1, 1, 9, 0, 101)].concat();
+
+    fs::create_dir_all(&header_dir).unwrap();
+    fs::create_dir_all(&raw_dir).unwrap();
+    fs::write(header_dir.join("bank.header"), header).unwrap();
+    fs::write(raw_dir.join("bank.raw"), [0; 8]).unwrap();
+
+    let output = Command::new(env!("CARGO_BIN_EXE_pixelconv"))
+        .args([&header_dir, &raw_dir, &dir.join("out")])
+        .output()
+        .unwrap();
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    let rows = stdout
+        .lines()
+        .skip(1)
+        .map(|line| line.split_whitespace().take(3).collect::<Vec<_>>())
+        .collect::<Vec<_>>();
+
+    assert_eq!(rows, [["0x00", "1", "yes"], ["0x09", "1", "no"]]);
+
+    fs::remove_dir_all(&dir).unwrap();
+}
@@ -0,0 +1,226 @@
+use std::io::Cursor;
+
+use common::{expanded, header_entry, rgba4444};
+use pixelconv::{
+    AlphaMode, BankHeader, ChannelOrder, DecodeOptions, Endian, PixelFormat, PixelOrder,
+};
+
+mod common;
+
+const PIXELS: [[u8; 4]; 4] = [[1, 2, 3, 4], [5, 6, 7, 8], [9, 10, 11, 12], [13, 14, 15, 0]];
+
+fn options() -> DecodeOptions {
+    DecodeOptions {
+        pixel_order: PixelOrder::Row,
+        swizzle: None,
+        packed: None,
+        force_format: None,
+        palette_offset: None,
+        palette_format: PixelFormat::Rgba4444,
+        gamma: None,
+        srgb: false,
+        channel_order: ChannelOrder::Argb,
+        alpha: AlphaMode::Straight,
+    }
+}
+
+/// A bank with a 2x2 texture, an empty entry and a 1x2 texture reusing the last row.
+fn bank() -> (BankHeader, Vec<u8>) {
+    let header = [
+        header_entry(2, 2, 0, 0, 100),
+        header_entry(0, 4, 0, 0, 101),
+        header_entry(1, 2, 0, 4, 102),
+    ]
+    .concat();
+    let raw = PIXELS.into_iter().flat_map(rgba4444).collect();
+
+    (BankHeader::from_bytes(header, Endian::Little).unwrap(), raw)
+}
+
+#[test]
+fn headers_list_their_entries() {
+    let (header, _) = bank();
+    let entries = header
+        .entries()
+        .map(|entry| (entry.width, entry.height, entry.offset, entry.texture_id))
+        .collect::<Vec<_>>();
+
+    assert_eq!(header.version, None);
+    assert_eq!(entries, [(2, 2, 0, 100), (0, 4, 0, 101), (1, 2, 4, 102)]);
+}
+
+#[test]
+fn textures_decode_row_by_row() {
+    let (header, raw) = bank();
+    let mut raw = Cursor::new(raw);
+    let image = header.entries[0]
+        .load_texture_from_reader(&mut raw, &options())
+        .unwrap();
+
+    assert_eq!(image.dimensions(), (2, 2));
+    assert_eq!(image.into_raw(), PIXELS.map(expanded).concat());
+
+    let image = header.entries[2]
+        .load_texture_from_reader(&mut raw, &options())
+        .unwrap();
+
+    assert_eq!(image.dimensions(), (1, 2));
+    assert_eq!(
+        image.into_raw(),
+        [expanded(PIXELS[2]), expanded(PIXELS[3])].concat()
+    );
+}
+
+#[test]
+fn empty_entries_are_rejected() {
+    let (header, raw) = bank();
+    let err = header.entries[1]
+        .load_texture_from_reader(&mut Cursor::new(raw), &options())
+        .unwrap_err();
+
+    assert_eq!(err.to_string(), "texture has no pixels (0x4)");
+}
+
+#[test]
+fn entries_reaching_past_the_raw_data_are_rejected() {
+    let header = BankHeader::from_bytes(header_entry(2, 2, 0, 4, 0), Endian::Little).unwrap();
+    let raw = PIXELS.into_iter().flat_map(rgba4444).collect::<Vec<_>>();
+    let err = header.entries[0]
+        .load_texture_from_reader(&mut Cursor::new(raw), &options())
+        .unwrap_err();
+
+    assert_eq!(
+        err.to_string(),
+        "texture wants bytes 0x4..0xc but the data is only 0x8 bytes"
+    );
+}
+
+#[test]
+fn column_major_textures_are_transposed() {
+    // A 2x3 texture, stored column by column
+    let columns = [
+        [1, 0, 0, 0],
+        [2, 0, 0, 0],
+        [3, 0, 0, 0],
+        [4, 0, 0, 0],
+        [5, 0, 0, 0],
+        [6, 0, 0, 0],
+    ];
+    let header = BankHeader::from_bytes(header_entry(2, 3, 0, 0, 0), Endian::Little).unwrap();
+    let raw = columns.into_iter().flat_map(rgba4444).collect::<Vec<_>>();
+    let options = DecodeOptions {
+        pixel_order: PixelOrder::Column,
+        ..options()
+    };
+    let image = header.entries[0]
+        .load_texture_from_reader(&mut Cursor::new(raw), &options)
+        .unwrap();
+    let rows = [0, 3, 1, 4, 2, 5].map(|index| expanded(columns[index]));
+
+    assert_eq!(image.dimensions(), (2, 3));
+    assert_eq!(image.into_raw(), rows.concat());
+}
+
+#[test]
+fn format_tables_keep_the_header_format() {
+    let mut header =
+        BankHeader::from_bytes(header_entry(1, 1, 0xff, 0, 2), Endian::Little).unwrap();
+
+    header.apply_format_table(&[3, 3, 0]);
+
+    let entry = &header.entries[0];
+    let image = entry
+        .load_texture_from_reader(&mut Cursor::new(rgba4444(PIXELS[0])), &options())
+        .unwrap();
+    let mut written = Vec::new();
+
+    entry.write_to(&mut written, Endian::Little).unwrap();
+
+    assert_eq!(entry.pixel_format, 0xff);
+    assert_eq!(entry.effective_pixel_format(), 0);
+    assert_eq!(image.into_raw(), expanded(PIXELS[0]));
+    assert_eq!(written, header_entry(1, 1, 0xff, 0, 2));
+}
+
+#[test]
+fn version_words_are_skipped() {
+    let header = [&2u32.to_le_bytes()[..], &header_entry(2, 2, 0, 0, 100)].concat();
+    let header = BankHeader::from_bytes(header, Endian::Little).unwrap();
+
+    assert_eq!(header.version, Some(2));
+    assert_eq!(header.entries[0].texture_id, 100);
+}
+
+#[test]
+fn unknown_version_words_are_read_as_entries() {
+    // 20 bytes, but the first word is the size of a 1x1 texture rather than a version
+    let header = [&header_entry(1, 1, 0, 0, 100)[..], &[0; 4]].concat();
+    let header = BankHeader::from_bytes(header, Endian::Little).unwrap();
+
+    assert_eq!(header.version, None);
+    assert_eq!(header.entries.len(), 1);
+    assert_eq!(header.entries[0].texture_id, 100);
+}
+
+#[test]
+fn combined_files_are_split_at_the_first_pixel_data() {
+    let raw = PIXELS.into_iter().flat_map(rgba4444).collect::<Vec<_>>();
+    let unversioned = [&header_entry(2, 2, 0, 16, 100)[..], &raw].concat();
+    let versioned = [
+        &2u32.to_le_bytes()[..],
+        &header_entry(2, 2, 0, 20, 100),
+        &raw,
+    ]
+    .concat();
+
+    for (file, version) in [(unversioned, None), (versioned, Some(2))] {
+        let header =
+            BankHeader::from_combined(&mut Cursor::new(file), None, Endian::Little).unwrap();
+
+        assert_eq!(header.version, version);
+        assert_eq!(header.entries.len(), 1);
+        assert_eq!(header.entries[0].texture_id, 100);
+    }
+}
+
+#[test]
+fn combined_header_sizes_beyond_the_file_are_rejected() {
+    let file = header_entry(2, 2, 0, 16, 100);
+
+    assert!(
+        BankHeader::from_combined(&mut Cursor::new(file), Some(u64::MAX), Endian::Little).is_err()
+    );
+}
+
+#[test]
+fn entries_roundtrip_in_both_byte_orders() {
+    let little = header_entry(0x102, 0x304, 1, 0x0506_0708, 0x90a);
+    let big = [
+        &[0x01, 0x02, 0x03, 0x04, 1, 0, 0, 0][..],
+        &[0x05, 0x06, 0x07, 0x08, 0x09, 0x0a, 0, 0],
+    ]
+    .concat();
+
+    for (endian, bytes) in [(Endian::Little, &little), (Endian::Big, &big)] {
+        let header = BankHeader::from_bytes(bytes.clone(), endian).unwrap();
+        let entry = &header.entries[0];
+        let mut written = Vec::new();
+
+        entry.write_to(&mut written, endian).unwrap();
+
+        assert_eq!(
+            (entry.width, entry.height, entry.offset, entry.texture_id),
+            (0x102, 0x304, 0x0506_0708, 0x90a)
+        );
+        assert_eq!(&written, bytes);
+    }
+
+    let header = BankHeader::from_bytes(little, Endian::Little).unwrap();
+    let mut written = Vec::new();
+
+    header.entries[0]
+        .write_to(&mut written, Endian::Big)
+        .unwrap();
+
+    assert_eq!(written, big);
+}
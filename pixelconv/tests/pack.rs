@@ -0,0 +1,206 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+use common::{header_entry, rgba4444, temp_dir};
+
+mod common;
+
+const PIXELS: [[u8; 4]; 4] = [[1, 2, 3, 4], [5, 6, 7, 8], [9, 10, 11, 12], [13, 14, 15, 0]];
+
+fn pixelconv(args: &[&Path]) -> bool {
+    Command::new(env!("CARGO_BIN_EXE_pixelconv"))
+        .args(args)
+        .output()
+        .unwrap()
+        .status
+        .success()
+}
+
+/// Converts a bank with `args`, packs it again and checks that the header
+/// and raw file are unchanged.
+fn assert_roundtrip(name: &str, header: Vec<u8>, args: &[&str]) {
+    let dir = temp_dir(name);
+    let (header_dir, raw_dir, out_dir) = (dir.join("headers"), dir.join("raws"), dir.join("out"));
+    let (packed_header_dir, packed_raw_dir) = (dir.join("packed_headers"), dir.join("packed_raws"));
+    let raw = PIXELS.into_iter().flat_map(rgba4444).collect::<Vec<_>>();
+
+    fs::create_dir_all(&header_dir).unwrap();
+    fs::create_dir_all(&raw_dir).unwrap();
+    fs::write(header_dir.join("bank.header"), &header).unwrap();
+    fs::write(raw_dir.join("bank.raw"), &raw).unwrap();
+
+    let convert_args = [&header_dir, &raw_dir, &out_dir]
+        .into_iter()
+        .map(PathBuf::as_path)
+        .chain(args.iter().map(Path::new))
+        .collect::<Vec<_>>();
+
+    assert!(pixelconv(&convert_args));
+    assert!(pixelconv(&[
+        Path::new("pack"),
+        &out_dir,
+        &packed_header_dir,
+        &packed_raw_dir,
+    ]));
+
+    assert_eq!(
+        fs::read(packed_header_dir.join("bank.header")).unwrap(),
+        header
+    );
+    assert_eq!(fs::read(packed_raw_dir.join("bank.raw")).unwrap(), raw);
+
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn packing_a_converted_bank_reproduces_it() {
+    let header = [
+        &3u32.to_le_bytes()[..],
+        &header_entry(2, 2, 0, 0, 100),
+        &header_entry(1, 2, 0, 4, 101),
+    ]
+    .concat();
+
+    assert_roundtrip("pack_roundtrip", header, &[]);
+}
+
+#[test]
+fn packing_reads_every_output_format() {
+    let header = header_entry(2, 2, 0, 0, 100);
+
+    for format in ["tga", "bmp", "dds"] {
+        assert_roundtrip(
+            &format!("pack_{format}"),
+            header.clone(),
+            &[&format!("--out-format={format}")],
+        );
+    }
+}
+
+#[test]
+fn packing_keeps_empty_entries() {
+    let texture = header_entry(2, 2, 0, 0, 100);
+    let empty = header_entry(0, 0, 0, 8, 101);
+
+    assert_roundtrip(
+        "pack_trailing_empty",
+        [texture.clone(), empty.clone()].concat(),
+        &[],
+    );
+    assert_roundtrip("pack_leading_empty", [empty, texture].concat(), &[]);
+}
+
+#[test]
+fn packing_keeps_big_endian_headers_big_endian() {
+    let header = [
+        &[0, 0, 0, 3][..],
+        &[0, 2, 0, 2, 0, 0, 0, 0, 0, 0, 0, 0, 0, 100, 0, 0],
+    ]
+    .concat();
+
+    assert_roundtrip("pack_roundtrip_big", header, &["--endian", "big"]);
+}
+
+#[test]
+fn packing_reverses_the_pixel_layout_options() {
+    let header = header_entry(2, 2, 0, 0, 100);
+
+    assert_roundtrip(
+        "pack_channel_order",
+        header.clone(),
+        &["--channel-order=bgra"],
+    );
+    assert_roundtrip(
+        "pack_pixel_order",
+        header.clone(),
+        &["--pixel-order=column"],
+    );
+    assert_roundtrip(
+        "pack_force_format",
+        header.clone(),
+        &["--force-format=rgb565"],
+    );
+    assert_roundtrip("pack_packed", header, &["--packed=4,4,4,4"]);
+}
+
+#[test]
+fn swizzled_textures_are_not_packed() {
+    let dir = temp_dir("pack_swizzle");
+    let (header_dir, raw_dir, out_dir) = (dir.join("headers"), dir.join("raws"), dir.join("out"));
+    let raw = PIXELS.into_iter().flat_map(rgba4444).collect::<Vec<_>>();
+
+    fs::create_dir_all(&header_dir).unwrap();
+    fs::create_dir_all(&raw_dir).unwrap();
+    fs::write(
+        header_dir.join("bank.header"),
+        header_entry(2, 2, 0, 0, 100),
+    )
+    .unwrap();
+    fs::write(raw_dir.join("bank.raw"), &raw).unwrap();
+
+    assert!(pixelconv(&[
+        &header_dir,
+        &raw_dir,
+        &out_dir,
+        Path::new("--swizzle=morton"),
+        Path::new("--tile-size=2x2"),
+    ]));
+    assert!(!pixelconv(&[
+        Path::new("pack"),
+        &out_dir,
+        &dir.join("packed_headers"),
+        &dir.join("packed_raws"),
+    ]));
+
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn packing_fails_if_a_bank_fails() {
+    let dir = temp_dir("pack_failure");
+    let bank_dir = dir.join("out/bank");
+
+    fs::create_dir_all(&bank_dir).unwrap();
+    // Texture 0 is missing
+    fs::write(bank_dir.join("01.json"), "{}").unwrap();
+
+    assert!(!pixelconv(&[
+        Path::new("pack"),
+        &dir.join("out"),
+        &dir.join("headers"),
+        &dir.join("raws"),
+    ]));
+
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn packing_fails_if_textures_of_the_manifest_are_missing() {
+    let dir = temp_dir("pack_partial");
+    let (header_dir, raw_dir, out_dir) = (dir.join("headers"), dir.join("raws"), dir.join("out"));
+    let raw = PIXELS.into_iter().flat_map(rgba4444).collect::<Vec<_>>();
+
+    fs::create_dir_all(&header_dir).unwrap();
+    fs::create_dir_all(&raw_dir).unwrap();
+    fs::write(
+        header_dir.join("bank.header"),
+        [header_entry(2, 2, 0, 0, 100), header_entry(1, 2, 0, 4, 101)].concat(),
+    )
+    .unwrap();
+    fs::write(raw_dir.join("bank.raw"), &raw).unwrap();
+
+    assert!(pixelconv(&[&header_dir, &raw_dir, &out_dir]));
+
+    fs::remove_file(out_dir.join("bank/01.json")).unwrap();
+    fs::remove_file(out_dir.join("bank/01.png")).unwrap();
+
+    assert!(!pixelconv(&[
+        Path::new("pack"),
+        &out_dir,
+        &dir.join("packed_headers"),
+        &dir.join("packed_raws"),
+    ]));
+
+    fs::remove_dir_all(&dir).unwrap();
+}
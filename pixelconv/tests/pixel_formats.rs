@@ -0,0 +1,118 @@
+use std::io::Cursor;
+
+use pixelconv::{
+    AlphaMode, ChannelOrder, DecodeOptions, Endian, PixelFormat, PixelOrder, TextureInfo,
+};
+
+fn options() -> DecodeOptions {
+    DecodeOptions {
+        pixel_order: PixelOrder::Row,
+        swizzle: None,
+        packed: None,
+        force_format: None,
+        palette_offset: None,
+        palette_format: PixelFormat::Rgba4444,
+        gamma: None,
+        srgb: false,
+        channel_order: ChannelOrder::Argb,
+        alpha: AlphaMode::Straight,
+    }
+}
+
+/// A 1x1 RGBA4444 texture at the start of the raw data.
+fn single_pixel_texture() -> TextureInfo {
+    let entry = [1, 0, 1, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0];
+
+    TextureInfo::from_reader(&mut &entry[..], Endian::Little).unwrap()
+}
+
+#[test]
+fn rgba4444_reads_green_blue_then_alpha_red() {
+    let pixel = PixelFormat::Rgba4444
+        .read_pixel(&mut &[0x12, 0x34][..])
+        .unwrap();
+
+    assert_eq!(pixel, [0x44, 0x11, 0x22, 0x33]);
+}
+
+#[test]
+fn rgba4444_roundtrips_through_write_pixel() {
+    let mut bytes = Vec::new();
+
+    PixelFormat::Rgba4444
+        .write_pixel([0x44, 0x11, 0x22, 0x33], &mut bytes)
+        .unwrap();
+
+    assert_eq!(bytes, [0x12, 0x34]);
+}
+
+#[test]
+fn channel_orders_name_the_nibbles_of_the_little_endian_word() {
+    let texture = single_pixel_texture();
+    // Read as a little-endian word this is 0x3412
+    let raw = [0x12, 0x34];
+    let decode = |channel_order| {
+        let options = DecodeOptions {
+            channel_order,
+            ..options()
+        };
+
+        texture
+            .read_pixel_data(&mut Cursor::new(raw), &options)
+            .unwrap()
+    };
+
+    assert_eq!(decode(ChannelOrder::Argb), [0x44, 0x11, 0x22, 0x33]);
+    assert_eq!(decode(ChannelOrder::Rgba), [0x33, 0x44, 0x11, 0x22]);
+    assert_eq!(decode(ChannelOrder::Bgra), [0x11, 0x44, 0x33, 0x22]);
+    assert_eq!(decode(ChannelOrder::Abgr), [0x22, 0x11, 0x44, 0x33]);
+}
+
+#[test]
+fn alpha_modes_are_applied_to_decoded_textures() {
+    let texture = single_pixel_texture();
+    let decode = |alpha| {
+        let options = DecodeOptions { alpha, ..options() };
+
+        texture
+            .load_texture_from_reader(&mut Cursor::new([0x12, 0x34]), &options)
+            .unwrap()
+            .into_raw()
+    };
+
+    assert_eq!(decode(AlphaMode::Straight), [0x44, 0x11, 0x22, 0x33]);
+    assert_eq!(decode(AlphaMode::Premultiplied), [14, 3, 7, 0x33]);
+    assert_eq!(decode(AlphaMode::Opaque), [0x44, 0x11, 0x22, 0xff]);
+}
+
+#[test]
+fn color_correction_can_be_undone() {
+    let texture = single_pixel_texture();
+    let decode = |gamma, srgb| {
+        let options = DecodeOptions {
+            gamma,
+            srgb,
+            ..options()
+        };
+
+        texture
+            .load_texture_from_reader(&mut Cursor::new([0x12, 0x34]), &options)
+            .unwrap()
+            .into_raw()
+    };
+
+    let mut corrected = decode(Some(2.2), true);
+
+    assert_ne!(corrected, decode(None, false));
+
+    pixelconv::undo_color_correction(&mut corrected, Some(2.2), true);
+
+    // The 8 bit values may be off by a little, but not enough to change the packed pixel
+    let mut packed = Vec::new();
+
+    PixelFormat::Rgba4444
+        .write_pixel(corrected.try_into().unwrap(), &mut packed)
+        .unwrap();
+
+    assert_eq!(packed, [0x12, 0x34]);
+}
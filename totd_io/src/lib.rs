@@ -0,0 +1,101 @@
+//! Reading of the binary formats shared by the tools.
+
+use std::io::{self, BufRead, ErrorKind, Read};
+
+/// Byte order of the integers in a file.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "clap", derive(clap::ValueEnum))]
+#[cfg_attr(
+    feature = "serde",
+    derive(serde::Serialize, serde::Deserialize),
+    serde(rename_all = "lowercase")
+)]
+pub enum Endian {
+    #[default]
+    Little,
+    /// Used by dumps of big-endian console variants
+    Big,
+}
+
+/// Helpers for reading the fields of the game's files, implemented for every reader.
+pub trait BinaryReader: Read {
+    fn read_u8(&mut self) -> io::Result<u8> {
+        let mut buf = [0; 1];
+
+        self.read_exact(&mut buf)?;
+
+        Ok(buf[0])
+    }
+
+    fn read_u16(&mut self, endian: Endian) -> io::Result<u16> {
+        let mut buf = [0; 2];
+
+        self.read_exact(&mut buf)?;
+
+        Ok(match endian {
+            Endian::Little => u16::from_le_bytes(buf),
+            Endian::Big => u16::from_be_bytes(buf),
+        })
+    }
+
+    fn read_u32(&mut self, endian: Endian) -> io::Result<u32> {
+        let mut buf = [0; 4];
+
+        self.read_exact(&mut buf)?;
+
+        Ok(match endian {
+            Endian::Little => u32::from_le_bytes(buf),
+            Endian::Big => u32::from_be_bytes(buf),
+        })
+    }
+
+    fn read_bytes(&mut self, amount: usize) -> io::Result<Vec<u8>> {
+        let mut bytes = vec![0; amount];
+
+        self.read_exact(&mut bytes)?;
+
+        Ok(bytes)
+    }
+
+    /// Reads `expected.len()` bytes and fails unless they equal `expected`.
+    fn read_magic(&mut self, expected: &[u8]) -> io::Result<()> {
+        let magic = self.read_bytes(expected.len())?;
+
+        if magic != expected {
+            return Err(io::Error::new(
+                ErrorKind::InvalidData,
+                format!(
+                    "expected magic {:?}, found {:?}",
+                    expected.escape_ascii().to_string(),
+                    magic.escape_ascii().to_string()
+                ),
+            ));
+        }
+
+        Ok(())
+    }
+
+    /// Reads a NUL-terminated UTF-8 string, consuming the terminator.
+    ///
+    /// Fails with `UnexpectedEof` if the terminator is missing, and with
+    /// `InvalidData` if the string is not UTF-8.
+    fn read_cstring(&mut self) -> io::Result<String>
+    where
+        Self: BufRead,
+    {
+        let mut string = Vec::new();
+
+        self.read_until(0, &mut string)?;
+
+        if string.pop() != Some(0) {
+            return Err(io::Error::new(
+                ErrorKind::UnexpectedEof,
+                "string is not NUL-terminated",
+            ));
+        }
+
+        String::from_utf8(string).map_err(|err| io::Error::new(ErrorKind::InvalidData, err))
+    }
+}
+
+impl<R: Read + ?Sized> BinaryReader for R {}
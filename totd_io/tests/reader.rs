@@ -0,0 +1,82 @@
+use std::io::{Cursor, ErrorKind};
+
+use totd_io::{BinaryReader, Endian};
+
+#[test]
+fn read_u8_reads_one_byte() {
+    let mut reader = Cursor::new([0xab, 0xcd]);
+
+    assert_eq!(reader.read_u8().unwrap(), 0xab);
+    assert_eq!(reader.position(), 1);
+}
+
+#[test]
+fn read_u16_honours_the_endian() {
+    let bytes = [0x12, 0x34];
+
+    assert_eq!(Cursor::new(bytes).read_u16(Endian::Little).unwrap(), 0x3412);
+    assert_eq!(Cursor::new(bytes).read_u16(Endian::Big).unwrap(), 0x1234);
+}
+
+#[test]
+fn read_u32_honours_the_endian() {
+    let bytes = [0x12, 0x34, 0x56, 0x78];
+
+    assert_eq!(
+        Cursor::new(bytes).read_u32(Endian::Little).unwrap(),
+        0x7856_3412
+    );
+    assert_eq!(
+        Cursor::new(bytes).read_u32(Endian::Big).unwrap(),
+        0x1234_5678
+    );
+}
+
+#[test]
+fn short_reads_fail() {
+    let errors = [
+        Cursor::new([]).read_u8().unwrap_err(),
+        Cursor::new([1]).read_u16(Endian::Little).unwrap_err(),
+        Cursor::new([1, 2, 3]).read_u32(Endian::Big).unwrap_err(),
+        Cursor::new([1, 2]).read_bytes(3).unwrap_err(),
+    ];
+
+    for err in errors {
+        assert_eq!(err.kind(), ErrorKind::UnexpectedEof);
+    }
+}
+
+#[test]
+fn read_bytes_reads_the_given_amount() {
+    let mut reader = Cursor::new([1, 2, 3, 4]);
+
+    assert_eq!(reader.read_bytes(3).unwrap(), [1, 2, 3]);
+    assert_eq!(reader.read_bytes(0).unwrap(), []);
+    assert_eq!(reader.position(), 3);
+}
+
+#[test]
+fn read_magic_accepts_only_the_expected_bytes() {
+    assert!(Cursor::new(b"HAB0rest").read_magic(b"HAB0").is_ok());
+
+    let err = Cursor::new(b"HAB1").read_magic(b"HAB0").unwrap_err();
+
+    assert_eq!(err.to_string(), r#"expected magic "HAB0", found "HAB1""#);
+}
+
+#[test]
+fn read_cstring_stops_after_the_terminator() {
+    let mut reader = Cursor::new(b"a.txt\0b.bin\0");
+
+    assert_eq!(reader.read_cstring().unwrap(), "a.txt");
+    assert_eq!(reader.read_cstring().unwrap(), "b.bin");
+}
+
+#[test]
+fn read_cstring_rejects_unterminated_and_invalid_strings() {
+    let unterminated = Cursor::new(b"a.txt").read_cstring().unwrap_err();
+    let invalid = Cursor::new(b"\xff\0").read_cstring().unwrap_err();
+
+    assert_eq!(unterminated.kind(), ErrorKind::UnexpectedEof);
+    assert_eq!(invalid.kind(), ErrorKind::InvalidData);
+}